@@ -17,7 +17,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    proxy.run().await;
+    proxy.run().await?;
 
     Ok(())
 }
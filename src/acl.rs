@@ -0,0 +1,220 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, ParseCidrError> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(ParseCidrError(()));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let prefix_len = match self.addr {
+            IpAddr::V4(_) => self.prefix_len + 96,
+            IpAddr::V6(_) => self.prefix_len,
+        };
+        let mask = u128::MAX
+            .checked_shl(128 - u32::from(prefix_len))
+            .unwrap_or(0);
+        let network = u128::from(to_ipv6(self.addr));
+        let ip = u128::from(to_ipv6(ip));
+        network & mask == ip & mask
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        Self { addr, prefix_len }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((addr, prefix_len)) = s.split_once('/') else {
+            let addr = s.parse::<IpAddr>().map_err(|_| ParseCidrError(()))?;
+            return Ok(Self::from(addr));
+        };
+        let addr = addr.parse().map_err(|_| ParseCidrError(()))?;
+        let prefix_len = prefix_len.parse().map_err(|_| ParseCidrError(()))?;
+        Self::new(addr, prefix_len)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCidrError(());
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid CIDR syntax")
+    }
+}
+
+impl Error for ParseCidrError {}
+
+#[derive(Debug, Default)]
+struct Rules {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    rules: Arc<RwLock<Rules>>,
+}
+
+impl AccessList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(&self, cidr: Cidr) {
+        self.rules.write().unwrap().allow.push(cidr);
+    }
+
+    pub fn deny(&self, cidr: Cidr) {
+        self.rules.write().unwrap().deny.push(cidr);
+    }
+
+    pub fn set_allow(&self, allow: Vec<Cidr>) {
+        self.rules.write().unwrap().allow = allow;
+    }
+
+    pub fn set_deny(&self, deny: Vec<Cidr>) {
+        self.rules.write().unwrap().deny = deny;
+    }
+
+    pub fn clear(&self) {
+        let mut rules = self.rules.write().unwrap();
+        rules.allow.clear();
+        rules.deny.clear();
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let rules = self.rules.read().unwrap();
+        if rules.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        rules.allow.is_empty() || rules.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn cidr(cidr: &str) -> Cidr {
+        cidr.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_rejects_invalid_cidrs() {
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+            "10.0.0/8",
+            "garbage",
+            "",
+        ] {
+            assert_eq!(
+                invalid.parse::<Cidr>(),
+                Err(ParseCidrError(())),
+                "{invalid}"
+            );
+        }
+        assert_eq!(cidr("10.0.0.1"), Cidr::new(ip("10.0.0.1"), 32).unwrap());
+        assert_eq!(cidr("::1").prefix_len(), 128);
+    }
+
+    #[test]
+    fn contains_respects_prefix_boundaries() {
+        let network = cidr("192.168.1.0/24");
+        assert!(network.contains(ip("192.168.1.0")));
+        assert!(network.contains(ip("192.168.1.255")));
+        assert!(!network.contains(ip("192.168.0.255")));
+        assert!(!network.contains(ip("192.168.2.0")));
+
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(cidr("10.0.0.1/32").contains(ip("10.0.0.1")));
+        assert!(!cidr("10.0.0.1/32").contains(ip("10.0.0.2")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn contains_matches_ipv4_mapped_peers() {
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(ip("10.1.2.3")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let access_list = AccessList::new();
+        access_list.allow(cidr("10.0.0.0/8"));
+        access_list.deny(cidr("10.0.0.5"));
+        assert!(access_list.is_allowed(ip("10.0.0.4")));
+        assert!(!access_list.is_allowed(ip("10.0.0.5")));
+        assert!(!access_list.is_allowed(ip("192.168.0.1")));
+    }
+
+    #[test]
+    fn empty_allow_list_allows_everything_not_denied() {
+        let access_list = AccessList::new();
+        assert!(access_list.is_allowed(ip("203.0.113.7")));
+        assert!(access_list.is_allowed(ip("::1")));
+        access_list.deny(cidr("203.0.113.0/24"));
+        assert!(!access_list.is_allowed(ip("203.0.113.7")));
+        assert!(access_list.is_allowed(ip("198.51.100.1")));
+        access_list.clear();
+        assert!(access_list.is_allowed(ip("203.0.113.7")));
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::net;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const UNIX_PREFIX: &str = "unix:";
+const UNNAMED: &str = "(unnamed)";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Inet(SocketAddr),
+    Unix(UnixAddress),
+}
+
+impl Address {
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.inet().map(|addr| addr.ip())
+    }
+
+    pub fn inet(&self) -> Option<SocketAddr> {
+        match self {
+            Address::Inet(addr) => Some(*addr),
+            Address::Unix(_) => None,
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        matches!(self, Address::Unix(_))
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(value: SocketAddr) -> Self {
+        Address::Inet(value)
+    }
+}
+
+impl From<UnixAddress> for Address {
+    fn from(value: UnixAddress) -> Self {
+        Address::Unix(value)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Inet(addr) => write!(f, "{addr}"),
+            Address::Unix(addr) => write!(f, "{addr}"),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(UNIX_PREFIX) {
+            return s.parse().map(Address::Unix);
+        }
+        s.parse()
+            .map(Address::Inet)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UnixAddress {
+    Pathname(PathBuf),
+    Abstract(Vec<u8>),
+    Unnamed,
+}
+
+impl UnixAddress {
+    pub fn pathname(path: impl AsRef<Path>) -> Self {
+        UnixAddress::Pathname(path.as_ref().to_path_buf())
+    }
+
+    pub fn abstract_name(name: impl AsRef<[u8]>) -> Self {
+        UnixAddress::Abstract(name.as_ref().to_vec())
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn from_std(addr: &net::SocketAddr) -> Self {
+        if let Some(path) = addr.as_pathname() {
+            return UnixAddress::Pathname(path.to_path_buf());
+        }
+        match abstract_name(addr) {
+            Some(name) => UnixAddress::Abstract(name.to_vec()),
+            None => UnixAddress::Unnamed,
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn from_socket2(addr: &socket2::SockAddr) -> Self {
+        if let Some(path) = addr.as_pathname() {
+            return UnixAddress::Pathname(path.to_path_buf());
+        }
+        match addr.as_abstract_namespace() {
+            Some(name) => UnixAddress::Abstract(name.to_vec()),
+            None => UnixAddress::Unnamed,
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn to_std(&self) -> io::Result<net::SocketAddr> {
+        match self {
+            UnixAddress::Pathname(path) => net::SocketAddr::from_pathname(path),
+            UnixAddress::Abstract(name) => from_abstract_name(name),
+            UnixAddress::Unnamed => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unnamed unix socket address",
+            )),
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn abstract_name(addr: &net::SocketAddr) -> Option<&[u8]> {
+    #[cfg(target_os = "android")]
+    use std::os::android::net::SocketAddrExt;
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+    addr.as_abstract_name()
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn abstract_name(_addr: &net::SocketAddr) -> Option<&[u8]> {
+    None
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn from_abstract_name(name: &[u8]) -> io::Result<net::SocketAddr> {
+    #[cfg(target_os = "android")]
+    use std::os::android::net::SocketAddrExt;
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+    net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn from_abstract_name(_name: &[u8]) -> io::Result<net::SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract unix socket addresses are only supported on linux",
+    ))
+}
+
+impl fmt::Display for UnixAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnixAddress::Pathname(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+            UnixAddress::Abstract(name) => {
+                write!(f, "{UNIX_PREFIX}@{}", String::from_utf8_lossy(name))
+            }
+            UnixAddress::Unnamed => write!(f, "{UNIX_PREFIX}{UNNAMED}"),
+        }
+    }
+}
+
+impl FromStr for UnixAddress {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix(UNIX_PREFIX) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid unix address",
+            ));
+        };
+        Ok(match rest {
+            UNNAMED => UnixAddress::Unnamed,
+            _ => match rest.strip_prefix('@') {
+                Some(name) => UnixAddress::Abstract(name.as_bytes().to_vec()),
+                None => UnixAddress::Pathname(PathBuf::from(rest)),
+            },
+        })
+    }
+}
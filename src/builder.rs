@@ -1,32 +1,109 @@
 use super::{Event, Proxy};
+use crate::acl::{AccessList, Cidr};
+use crate::error::Error;
+use crate::fault::FaultInjector;
+use crate::handle::ProxyHandle;
+use crate::route::RouteBuilder;
 use crate::{tcp, udp, ProxyEventManager};
-use tokio::io;
 use tokio::net::ToSocketAddrs;
 use tokio::sync::mpsc;
 
 #[derive(Debug)]
 pub struct ProxyBuilder {
-    tcp: tcp::ProxyBuilder,
-    udp: udp::ProxyBuilder,
+    tcp: Option<tcp::ProxyBuilder>,
+    udp: Option<udp::ProxyBuilder>,
+    routes: Vec<(String, RouteBuilder)>,
+    access_list: AccessList,
+    faults: FaultInjector,
 
     event_sender: Option<mpsc::Sender<Event>>,
 }
 
 impl ProxyBuilder {
     pub fn new() -> Self {
+        let access_list = AccessList::new();
+        let faults = FaultInjector::default();
         Self {
-            tcp: tcp::ProxyBuilder::new(),
-            udp: udp::ProxyBuilder::new(),
+            tcp: Some(
+                tcp::ProxyBuilder::new()
+                    .access_list(access_list.clone())
+                    .faults(faults.clone()),
+            ),
+            udp: Some(
+                udp::ProxyBuilder::new()
+                    .access_list(access_list.clone())
+                    .faults(faults.clone()),
+            ),
+            routes: Vec::new(),
+            access_list,
+            faults,
 
             event_sender: None,
         }
     }
+
+    pub fn tcp(mut self, configure: impl FnOnce(tcp::ProxyBuilder) -> tcp::ProxyBuilder) -> Self {
+        let tcp = self.tcp.take().unwrap_or_else(|| {
+            tcp::ProxyBuilder::new()
+                .access_list(self.access_list.clone())
+                .faults(self.faults.clone())
+        });
+        self.tcp = Some(configure(tcp));
+        self
+    }
+
+    pub fn udp(mut self, configure: impl FnOnce(udp::ProxyBuilder) -> udp::ProxyBuilder) -> Self {
+        let udp = self.udp.take().unwrap_or_else(|| {
+            udp::ProxyBuilder::new()
+                .access_list(self.access_list.clone())
+                .faults(self.faults.clone())
+        });
+        self.udp = Some(configure(udp));
+        self
+    }
+
+    pub fn tcp_route(
+        mut self,
+        name: impl Into<String>,
+        configure: impl FnOnce(tcp::ProxyBuilder) -> tcp::ProxyBuilder,
+    ) -> Self {
+        let tcp = tcp::ProxyBuilder::new()
+            .access_list(self.access_list.clone())
+            .faults(self.faults.clone());
+        self.routes
+            .push((name.into(), RouteBuilder::Tcp(Box::new(configure(tcp)))));
+        self
+    }
+
+    pub fn udp_route(
+        mut self,
+        name: impl Into<String>,
+        configure: impl FnOnce(udp::ProxyBuilder) -> udp::ProxyBuilder,
+    ) -> Self {
+        let udp = udp::ProxyBuilder::new()
+            .access_list(self.access_list.clone())
+            .faults(self.faults.clone());
+        self.routes
+            .push((name.into(), RouteBuilder::Udp(Box::new(configure(udp)))));
+        self
+    }
+
+    pub fn disable_tcp(mut self) -> Self {
+        self.tcp = None;
+        self
+    }
+
+    pub fn disable_udp(mut self) -> Self {
+        self.udp = None;
+        self
+    }
+
     pub fn local_addrs<A: ToSocketAddrs + Send + 'static + Clone>(
         mut self,
         local_addrs: A,
     ) -> Self {
-        self.tcp = self.tcp.local_addrs(local_addrs.clone());
-        self.udp = self.udp.local_addrs(local_addrs);
+        self.tcp = self.tcp.map(|tcp| tcp.local_addrs(local_addrs.clone()));
+        self.udp = self.udp.map(|udp| udp.local_addrs(local_addrs));
         self
     }
 
@@ -34,47 +111,107 @@ impl ProxyBuilder {
         mut self,
         remote_addrs: A,
     ) -> Self {
-        self.tcp = self.tcp.remote_addrs(remote_addrs.clone());
-        self.udp = self.udp.remote_addrs(remote_addrs);
+        self.tcp = self.tcp.map(|tcp| tcp.remote_addrs(remote_addrs.clone()));
+        self.udp = self.udp.map(|udp| udp.remote_addrs(remote_addrs));
         self
     }
 
     pub fn event_sender(mut self, event_sender: mpsc::Sender<Event>) -> Self {
-        self.event_sender = Some(event_sender.clone());
+        self.event_sender = Some(event_sender);
         self
     }
 
     pub fn buffer_size(mut self, buffer_size: usize) -> Self {
-        self.tcp = self.tcp.buffer_size(buffer_size);
-        self.udp = self.udp.buffer_size(buffer_size);
+        self.tcp = self.tcp.map(|tcp| tcp.buffer_size(buffer_size));
+        self.udp = self.udp.map(|udp| udp.buffer_size(buffer_size));
+        self
+    }
+
+    pub fn allow(self, cidr: Cidr) -> Self {
+        self.access_list.allow(cidr);
+        self
+    }
+
+    pub fn deny(self, cidr: Cidr) -> Self {
+        self.access_list.deny(cidr);
+        self
+    }
+
+    pub fn access_list(mut self, access_list: AccessList) -> Self {
+        self.tcp = self.tcp.map(|tcp| tcp.access_list(access_list.clone()));
+        self.udp = self.udp.map(|udp| udp.access_list(access_list.clone()));
+        self.routes = self
+            .routes
+            .into_iter()
+            .map(|(name, route)| (name, route.access_list(access_list.clone())))
+            .collect();
+        self.access_list = access_list;
+        self
+    }
+
+    pub fn faults(mut self, faults: FaultInjector) -> Self {
+        self.tcp = self.tcp.map(|tcp| tcp.faults(faults.clone()));
+        self.udp = self.udp.map(|udp| udp.faults(faults.clone()));
+        self.routes = self
+            .routes
+            .into_iter()
+            .map(|(name, route)| (name, route.faults(faults.clone())))
+            .collect();
+        self.faults = faults;
         self
     }
 
-    pub async fn build(self) -> io::Result<Proxy> {
-        if let Some(event_sender) = self.event_sender {
-            let (tcp_event_sender, tcp_event_receiver) = mpsc::channel(event_sender.max_capacity());
-            let (udp_event_sender, udp_event_receiver) = mpsc::channel(event_sender.max_capacity());
-            let tcp = self.tcp.event_sender(tcp_event_sender).build().await?;
-            let udp = self.udp.event_sender(udp_event_sender).build().await?;
-            let event_manager = Some(ProxyEventManager {
-                event_sender,
-                tcp_event_receiver,
-                udp_event_receiver,
-            });
-            Ok(Proxy {
-                tcp,
-                udp,
-                event_manager,
-            })
-        } else {
-            let tcp = self.tcp.build().await?;
-            let udp = self.udp.build().await?;
-            let event_manager = None;
-            Ok(Proxy {
-                tcp,
-                udp,
-                event_manager,
-            })
+    pub async fn build(self) -> Result<Proxy, Error> {
+        if self.tcp.is_none() && self.udp.is_none() && self.routes.is_empty() {
+            return Err(Error::InvalidConfig("tcp, udp and routes are all disabled"));
         }
+        for (index, (name, _)) in self.routes.iter().enumerate() {
+            if self.routes[..index].iter().any(|(other, _)| other == name) {
+                return Err(Error::InvalidConfig("duplicate route name"));
+            }
+        }
+        let capacity = self
+            .event_sender
+            .as_ref()
+            .map_or(1, |event_sender| event_sender.max_capacity());
+        let (tcp_event_sender, tcp_event_receiver) = mpsc::channel(capacity);
+        let (udp_event_sender, udp_event_receiver) = mpsc::channel(capacity);
+        let has_events = self.event_sender.is_some();
+        let tcp = match self.tcp {
+            Some(tcp) if has_events => Some(tcp.event_sender(tcp_event_sender).build().await?),
+            Some(tcp) => Some(tcp.build().await?),
+            None => None,
+        };
+        let udp = match self.udp {
+            Some(udp) if has_events => Some(udp.event_sender(udp_event_sender).build().await?),
+            Some(udp) => Some(udp.build().await?),
+            None => None,
+        };
+        let mut routes = Vec::with_capacity(self.routes.len());
+        let mut route_forwards = Vec::with_capacity(self.routes.len());
+        for (name, route) in self.routes {
+            let (route, forward) = route.build(&name, self.event_sender.as_ref()).await?;
+            routes.push((name, route));
+            route_forwards.extend(forward);
+        }
+        let event_manager = self.event_sender.map(|event_sender| ProxyEventManager {
+            event_sender,
+            tcp_event_receiver,
+            udp_event_receiver,
+            route_forwards,
+        });
+        Ok(Proxy {
+            tcp,
+            udp,
+            routes,
+            handle: ProxyHandle::new(self.access_list, self.faults),
+            event_manager,
+        })
+    }
+}
+
+impl Default for ProxyBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,363 @@
+use crate::writer::FileWriter;
+use crate::Direction;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+const LINKTYPE_RAW: u16 = 101;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const MAX_SEGMENT: usize = 65000;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+fn normalize(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        addr => addr,
+    }
+}
+
+fn same_family(src: IpAddr, dst: IpAddr) -> (IpAddr, IpAddr) {
+    match (normalize(src), normalize(dst)) {
+        (IpAddr::V4(src), IpAddr::V6(dst)) => (IpAddr::V6(src.to_ipv6_mapped()), IpAddr::V6(dst)),
+        (IpAddr::V6(src), IpAddr::V4(dst)) => (IpAddr::V6(src), IpAddr::V6(dst.to_ipv6_mapped())),
+        pair => pair,
+    }
+}
+
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    let mut odd = None;
+    for &byte in chunks.iter().flat_map(|c| c.iter()) {
+        match odd.take() {
+            Some(high) => sum += u32::from(u16::from_be_bytes([high, byte])),
+            None => odd = Some(byte),
+        }
+    }
+    if let Some(high) = odd {
+        sum += u32::from(u16::from_be_bytes([high, 0]));
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn transport_checksum(protocol: u8, chunks: &[&[u8]]) -> u16 {
+    match checksum(chunks) {
+        // A zero UDP checksum means "no checksum", so a computed zero is sent as all ones.
+        0 if protocol == IPPROTO_UDP => 0xffff,
+        sum => sum,
+    }
+}
+
+fn ip_packet(
+    src: SocketAddr,
+    dst: SocketAddr,
+    protocol: u8,
+    mut segment: Vec<u8>,
+) -> Option<Vec<u8>> {
+    let checksum_offset = if protocol == IPPROTO_TCP { 16 } else { 6 };
+    let length = u16::try_from(segment.len()).ok()?;
+    Some(match same_family(src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let pseudo = [
+                &src.octets()[..],
+                &dst.octets(),
+                &[0, protocol],
+                &length.to_be_bytes(),
+            ]
+            .concat();
+            let sum = transport_checksum(protocol, &[&pseudo, &segment]);
+            segment[checksum_offset..checksum_offset + 2].copy_from_slice(&sum.to_be_bytes());
+            let mut header = vec![0x45, 0];
+            header.extend_from_slice(&length.checked_add(20)?.to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            let sum = checksum(&[&header]);
+            header[10..12].copy_from_slice(&sum.to_be_bytes());
+            header.extend_from_slice(&segment);
+            header
+        }
+        (src, dst) => {
+            let src = match src {
+                IpAddr::V6(ip) => ip,
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            };
+            let dst = match dst {
+                IpAddr::V6(ip) => ip,
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            };
+            let pseudo = [
+                &src.octets()[..],
+                &dst.octets(),
+                &u32::from(length).to_be_bytes(),
+                &[0, 0, 0, protocol],
+            ]
+            .concat();
+            let sum = transport_checksum(protocol, &[&pseudo, &segment]);
+            segment[checksum_offset..checksum_offset + 2].copy_from_slice(&sum.to_be_bytes());
+            let mut header = vec![0x60, 0, 0, 0];
+            header.extend_from_slice(&length.to_be_bytes());
+            header.extend_from_slice(&[protocol, 64]);
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            header.extend_from_slice(&segment);
+            header
+        }
+    })
+}
+
+fn tcp_segment(
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    segment
+}
+
+fn udp_datagram(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + payload.len());
+    datagram.extend_from_slice(&src.port().to_be_bytes());
+    datagram.extend_from_slice(&dst.port().to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total_len = (12 + body.len() + padding) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&[0; 3][..padding])?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+#[derive(Debug, Clone)]
+pub struct Capture {
+    writer: FileWriter,
+    started_at: (Duration, Instant),
+}
+
+impl Capture {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut section = Vec::new();
+        section.extend_from_slice(&0x1a2b3c4d_u32.to_le_bytes());
+        section.extend_from_slice(&1_u16.to_le_bytes());
+        section.extend_from_slice(&0_u16.to_le_bytes());
+        section.extend_from_slice(&(-1_i64).to_le_bytes());
+        write_block(&mut writer, 0x0a0d0d0a, &section)?;
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        interface.extend_from_slice(&0_u16.to_le_bytes());
+        interface.extend_from_slice(&0_u32.to_le_bytes());
+        write_block(&mut writer, 1, &interface)?;
+        writer.flush()?;
+        Ok(Self {
+            writer: FileWriter::spawn(writer)?,
+            started_at: (
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+                Instant::now(),
+            ),
+        })
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_packet(&self, packet: &[u8]) {
+        let (epoch, started_at) = self.started_at;
+        let micros = (epoch + started_at.elapsed()).as_micros() as u64;
+        let mut body = Vec::with_capacity(20 + packet.len());
+        body.extend_from_slice(&0_u32.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet);
+        let mut block = Vec::with_capacity(12 + body.len() + 3);
+        let _ = write_block(&mut block, 6, &body);
+        self.writer.write(block);
+    }
+
+    pub(crate) fn udp(&self, src: SocketAddr, dst: SocketAddr, payload: &[u8]) {
+        let empty = payload.is_empty().then_some(payload);
+        for payload in payload.chunks(MAX_SEGMENT).chain(empty) {
+            let datagram = udp_datagram(src, dst, payload);
+            if let Some(packet) = ip_packet(src, dst, IPPROTO_UDP, datagram) {
+                self.write_packet(&packet);
+            }
+        }
+    }
+
+    pub(crate) fn tcp_flow(&self, client: SocketAddr, server: SocketAddr) -> TcpFlow {
+        let flow = TcpFlow {
+            capture: self.clone(),
+            client,
+            server,
+            seqs: Mutex::new([0, 0]),
+        };
+        flow.handshake();
+        flow
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TcpFlow {
+    capture: Capture,
+    client: SocketAddr,
+    server: SocketAddr,
+    seqs: Mutex<[u32; 2]>,
+}
+
+impl TcpFlow {
+    fn endpoints(&self, direction: Direction) -> (SocketAddr, SocketAddr, usize) {
+        match direction {
+            Direction::Upload => (self.client, self.server, 0),
+            Direction::Download => (self.server, self.client, 1),
+        }
+    }
+
+    fn segment(&self, direction: Direction, flags: u8, payload: &[u8], advance: u32) {
+        let (src, dst, index) = self.endpoints(direction);
+        let mut seqs = self.seqs.lock().unwrap();
+        let seq = seqs[index];
+        let ack = if flags & TCP_ACK != 0 {
+            seqs[1 - index]
+        } else {
+            0
+        };
+        seqs[index] = seq.wrapping_add(advance);
+        drop(seqs);
+        let segment = tcp_segment(src, dst, seq, ack, flags, payload);
+        if let Some(packet) = ip_packet(src, dst, IPPROTO_TCP, segment) {
+            self.capture.write_packet(&packet);
+        }
+    }
+
+    fn handshake(&self) {
+        self.segment(Direction::Upload, TCP_SYN, &[], 1);
+        self.segment(Direction::Download, TCP_SYN | TCP_ACK, &[], 1);
+        self.segment(Direction::Upload, TCP_ACK, &[], 0);
+    }
+
+    pub(crate) fn data(&self, direction: Direction, payload: &[u8]) {
+        for payload in payload.chunks(MAX_SEGMENT) {
+            self.segment(direction, TCP_PSH | TCP_ACK, payload, payload.len() as u32);
+        }
+    }
+
+    pub(crate) fn close(&self, direction: Direction) {
+        self.segment(direction, TCP_FIN | TCP_ACK, &[], 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn checksum_matches_known_ipv4_header() {
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(checksum(&[&header]), 0xb861);
+    }
+
+    #[test]
+    fn zero_udp_checksum_is_sent_as_all_ones() {
+        let (src, dst) = (addr("10.0.0.1:1000"), addr("10.0.0.2:2000"));
+        let probe = ip_packet(src, dst, IPPROTO_UDP, udp_datagram(src, dst, &[0, 0])).unwrap();
+        // A payload equal to the checksum of the zero payload folds the sum to zero.
+        let payload = [probe[26], probe[27]];
+        let packet = ip_packet(src, dst, IPPROTO_UDP, udp_datagram(src, dst, &payload)).unwrap();
+        assert_eq!(&packet[26..28], [0xff, 0xff]);
+
+        let v6 = (addr("[2001:db8::1]:1000"), addr("[2001:db8::2]:2000"));
+        let packet = ip_packet(v6.0, v6.1, IPPROTO_UDP, udp_datagram(v6.0, v6.1, b"x")).unwrap();
+        assert_ne!(&packet[46..48], [0, 0]);
+    }
+
+    #[test]
+    fn oversized_segments_are_skipped() {
+        let (src, dst) = (addr("10.0.0.1:1000"), addr("10.0.0.2:2000"));
+        assert!(ip_packet(src, dst, IPPROTO_UDP, vec![0; 65535 - 19]).is_none());
+        assert!(ip_packet(src, dst, IPPROTO_UDP, vec![0; 65536]).is_none());
+        assert!(ip_packet(src, dst, IPPROTO_UDP, vec![0; 65535 - 20]).is_some());
+    }
+
+    #[test]
+    fn capture_writes_pcapng_blocks() {
+        let path = std::env::temp_dir().join(format!("proxy-rs-capture-{}", std::process::id()));
+        let capture = Capture::create(&path).unwrap();
+        capture.udp(addr("10.0.0.1:1000"), addr("10.0.0.2:2000"), b"hello");
+        capture.flush().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let block_type = u32_at(&bytes, offset);
+            let len = u32_at(&bytes, offset + 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(&bytes, offset + len - 4) as usize, len);
+            blocks.push((block_type, &bytes[offset + 8..offset + len - 4]));
+            offset += len;
+        }
+        assert_eq!(offset, bytes.len());
+        let [(0x0a0d0d0a, section), (1, interface), (6, packet)] = blocks.as_slice() else {
+            panic!("unexpected blocks: {blocks:?}");
+        };
+        assert_eq!(u32_at(section, 0), 0x1a2b3c4d);
+        assert_eq!(&section[4..8], [1, 0, 0, 0]);
+        assert_eq!(
+            u16::from_le_bytes([interface[0], interface[1]]),
+            LINKTYPE_RAW
+        );
+
+        let captured_len = u32_at(packet, 12) as usize;
+        assert_eq!(captured_len, 20 + 8 + 5);
+        assert_eq!(u32_at(packet, 16) as usize, captured_len);
+        let ip = &packet[20..20 + captured_len];
+        assert_eq!(ip[0], 0x45);
+        assert_eq!(ip[9], IPPROTO_UDP);
+        assert_eq!(checksum(&[&ip[..20]]), 0);
+        assert_eq!(&ip[28..], b"hello");
+    }
+}
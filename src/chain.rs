@@ -0,0 +1,369 @@
+use crate::address::Address;
+use crate::error::Error;
+use crate::http::{self, ResponseHead};
+use crate::net;
+use crate::net::Socket;
+use crate::socks::{self, Target};
+use crate::transport::Connector;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Socks5,
+    HttpConnect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamProxy {
+    kind: Kind,
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+impl UpstreamProxy {
+    pub fn socks5(addr: impl Into<String>) -> Self {
+        Self {
+            kind: Kind::Socks5,
+            addr: addr.into(),
+            credentials: None,
+        }
+    }
+
+    pub fn http_connect(addr: impl Into<String>) -> Self {
+        Self {
+            kind: Kind::HttpConnect,
+            addr: addr.into(),
+            credentials: None,
+        }
+    }
+
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if let (Kind::Socks5, Some((username, password))) = (self.kind, &self.credentials) {
+            if username.len() > usize::from(u8::MAX) || password.len() > usize::from(u8::MAX) {
+                return Err(Error::InvalidConfig(
+                    "socks5 username and password must be at most 255 bytes",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn tunnel(&self, stream: &mut Socket, target: &Target) -> io::Result<()> {
+        match self.kind {
+            Kind::Socks5 => self.socks5_tunnel(stream, target).await,
+            Kind::HttpConnect => self.http_tunnel(stream, target).await,
+        }
+    }
+
+    async fn socks5_tunnel(&self, stream: &mut Socket, target: &Target) -> io::Result<()> {
+        let method = match self.credentials {
+            Some(_) => socks::METHOD_USERNAME_PASSWORD,
+            None => socks::METHOD_NO_AUTH,
+        };
+        stream.write_all(&[socks::VERSION, 1, method]).await?;
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await?;
+        if choice[0] != socks::VERSION || choice[1] != method {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "socks5 proxy rejected authentication method",
+            ));
+        }
+        if let Some((username, password)) = &self.credentials {
+            let field_len = |field: &str| {
+                u8::try_from(field.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "socks5 credential too long")
+                })
+            };
+            let mut request = vec![socks::AUTH_VERSION, field_len(username)?];
+            request.extend_from_slice(username.as_bytes());
+            request.push(field_len(password)?);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+            let mut status = [0; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "socks5 proxy rejected credentials",
+                ));
+            }
+        }
+
+        let mut request = vec![socks::VERSION, socks::COMMAND_CONNECT, 0x00];
+        target.encode(&mut request);
+        stream.write_all(&request).await?;
+        let mut head = [0; 3];
+        stream.read_exact(&mut head).await?;
+        if head[0] != socks::VERSION {
+            return Err(invalid_data("invalid socks5 reply"));
+        }
+        Target::read(stream).await?;
+        match head[1] {
+            socks::REPLY_SUCCEEDED => Ok(()),
+            socks::REPLY_CONNECTION_REFUSED => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "socks5 proxy connection refused",
+            )),
+            code => Err(io::Error::other(format!(
+                "socks5 proxy replied {code:#04x}"
+            ))),
+        }
+    }
+
+    async fn http_tunnel(&self, stream: &mut Socket, target: &Target) -> io::Result<()> {
+        let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some((username, password)) = &self.credentials {
+            let token = base64(format!("{username}:{password}").as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut buffer = Vec::new();
+        let response = loop {
+            if buffer.len() >= http::MAX_HEAD {
+                return Err(invalid_data("response head too large"));
+            }
+            let byte = stream.read_u8().await?;
+            buffer.push(byte);
+            if buffer.ends_with(b"\r\n\r\n") {
+                match ResponseHead::parse(&buffer)? {
+                    Some((response, _)) => break response,
+                    None => return Err(invalid_data("incomplete response head")),
+                }
+            }
+        };
+        match response.status {
+            200..=299 => Ok(()),
+            407 => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "http proxy requires authentication",
+            )),
+            status => Err(io::Error::other(format!("http proxy replied {status}"))),
+        }
+    }
+}
+
+fn address(authority: &str) -> io::Result<Target> {
+    if let Ok(addr) = authority.parse() {
+        return Ok(Target::Socket(addr));
+    }
+    let (host, port) =
+        http::parse_authority(authority).ok_or_else(|| invalid_data("invalid proxy address"))?;
+    Ok(Target::Domain(host, port))
+}
+
+async fn dial(connector: Option<&dyn Connector>, target: &Address) -> io::Result<Socket> {
+    match connector {
+        Some(connector) => connector.connect(target).await.map(Socket::Custom),
+        None => Socket::connect(target).await,
+    }
+}
+
+async fn dial_proxy(connector: Option<&dyn Connector>, addr: &str) -> io::Result<Socket> {
+    let addrs = match address(addr)? {
+        Target::Socket(addr) => vec![addr],
+        Target::Domain(host, port) => net::resolve(connector, &host, port).await?,
+    };
+    let mut last_error = None;
+    for addr in addrs {
+        match dial(connector, &Address::Inet(addr)).await {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no proxy addresses")))
+}
+
+pub(crate) async fn connect(
+    connector: Option<&dyn Connector>,
+    upstream_proxies: &[UpstreamProxy],
+    target: &Address,
+) -> io::Result<Socket> {
+    let Some((first, rest)) = upstream_proxies.split_first() else {
+        return dial(connector, target).await;
+    };
+    let Some(target) = target.inet() else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cannot tunnel to a unix socket through an upstream proxy",
+        ));
+    };
+    let mut stream = dial_proxy(connector, &first.addr).await?;
+    let mut hop = first;
+    for next in rest {
+        hop.tunnel(&mut stream, &address(&next.addr)?).await?;
+        hop = next;
+    }
+    hop.tunnel(&mut stream, &Target::Socket(target)).await?;
+    Ok(stream)
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn pair() -> (Socket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (Socket::Tcp(client), server)
+    }
+
+    fn target() -> Target {
+        Target::Socket("192.0.2.1:443".parse::<SocketAddr>().unwrap())
+    }
+
+    #[test]
+    fn base64_matches_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(base64(input.as_bytes()), expected);
+        }
+    }
+
+    #[test]
+    fn long_socks5_credentials_are_rejected() {
+        let long = "x".repeat(256);
+        let proxy = UpstreamProxy::socks5("127.0.0.1:1080").credentials(long.clone(), "pw");
+        assert!(matches!(proxy.validate(), Err(Error::InvalidConfig(_))));
+        let proxy = UpstreamProxy::socks5("127.0.0.1:1080").credentials("user", long.clone());
+        assert!(matches!(proxy.validate(), Err(Error::InvalidConfig(_))));
+        let proxy = UpstreamProxy::http_connect("127.0.0.1:8080").credentials(long, "pw");
+        assert!(proxy.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn socks5_handshake_with_credentials() {
+        let (mut client, mut server) = pair().await;
+        let proxy = UpstreamProxy::socks5("127.0.0.1:1080").credentials("user", "secret");
+        let server = tokio::spawn(async move {
+            let mut greeting = [0; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(
+                greeting,
+                [socks::VERSION, 1, socks::METHOD_USERNAME_PASSWORD]
+            );
+            server
+                .write_all(&[socks::VERSION, socks::METHOD_USERNAME_PASSWORD])
+                .await
+                .unwrap();
+            let mut auth = [0; 13];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x06secret");
+            server.write_all(&[socks::AUTH_VERSION, 0]).await.unwrap();
+            let mut request = [0; 3];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [socks::VERSION, socks::COMMAND_CONNECT, 0]);
+            let target = Target::read(&mut server).await.unwrap();
+            assert_eq!(target, self::target());
+            let mut reply = vec![socks::VERSION, socks::REPLY_SUCCEEDED, 0];
+            target.encode(&mut reply);
+            server.write_all(&reply).await.unwrap();
+        });
+        proxy.tunnel(&mut client, &target()).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_rejected_credentials() {
+        let (mut client, mut server) = pair().await;
+        let proxy = UpstreamProxy::socks5("127.0.0.1:1080").credentials("user", "wrong");
+        tokio::spawn(async move {
+            let mut greeting = [0; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server
+                .write_all(&[socks::VERSION, socks::METHOD_USERNAME_PASSWORD])
+                .await
+                .unwrap();
+            let mut auth = [0; 12];
+            server.read_exact(&mut auth).await.unwrap();
+            server.write_all(&[socks::AUTH_VERSION, 1]).await.unwrap();
+        });
+        let error = proxy.tunnel(&mut client, &target()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn http_connect_handshake_with_credentials() {
+        let (mut client, mut server) = pair().await;
+        let proxy = UpstreamProxy::http_connect("127.0.0.1:8080").credentials("user", "secret");
+        let server = tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(server.read_u8().await.unwrap());
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.starts_with("CONNECT 192.0.2.1:443 HTTP/1.1\r\n"));
+            assert!(request.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+            server
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        proxy.tunnel(&mut client, &target()).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_connect_requires_authentication() {
+        let (mut client, mut server) = pair().await;
+        let proxy = UpstreamProxy::http_connect("127.0.0.1:8080");
+        tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(server.read_u8().await.unwrap());
+            }
+            server
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        let error = proxy.tunnel(&mut client, &target()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
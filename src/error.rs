@@ -0,0 +1,60 @@
+use std::fmt;
+use std::io;
+use tokio::task::JoinSet;
+
+#[derive(Debug)]
+pub enum Error {
+    Resolve(io::Error),
+    NoLocalAddresses,
+    NoRemoteAddresses,
+    Bind(io::Error),
+    Tls(io::Error),
+    InvalidConfig(&'static str),
+}
+
+impl Error {
+    pub(crate) async fn resolve_all<T: 'static>(
+        handles: &mut JoinSet<io::Result<T>>,
+    ) -> Result<Vec<T>, Error> {
+        let mut resolved = Vec::new();
+        while let Some(result) = handles.join_next().await {
+            let result = result.map_err(|error| Error::Resolve(io::Error::other(error)))?;
+            resolved.push(result.map_err(Error::Resolve)?);
+        }
+        Ok(resolved)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Resolve(error) => write!(f, "address resolution failed: {error}"),
+            Error::NoLocalAddresses => write!(f, "no local addresses to listen on"),
+            Error::NoRemoteAddresses => write!(f, "no remote addresses to forward to"),
+            Error::Bind(error) => write!(f, "bind failed: {error}"),
+            Error::Tls(error) => write!(f, "invalid tls configuration: {error}"),
+            Error::InvalidConfig(reason) => write!(f, "invalid configuration: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Resolve(error) | Error::Bind(error) | Error::Tls(error) => Some(error),
+            Error::NoLocalAddresses | Error::NoRemoteAddresses | Error::InvalidConfig(_) => None,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        let kind = match &value {
+            Error::Resolve(error) | Error::Bind(error) | Error::Tls(error) => error.kind(),
+            Error::NoLocalAddresses | Error::NoRemoteAddresses | Error::InvalidConfig(_) => {
+                io::ErrorKind::InvalidInput
+            }
+        };
+        io::Error::new(kind, value)
+    }
+}
@@ -1,7 +1,33 @@
+use crate::record::Protocol;
 use crate::{tcp, udp};
+use std::io;
 
 #[derive(Debug)]
 pub enum Event {
     Tcp(tcp::Event),
     Udp(udp::Event),
+    Stopped(Stopped),
+    Route(RouteEvent),
+}
+
+#[derive(Debug)]
+pub struct Stopped {
+    pub protocol: Protocol,
+    pub result: io::Result<()>,
+}
+impl From<Stopped> for Event {
+    fn from(value: Stopped) -> Self {
+        Event::Stopped(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct RouteEvent {
+    pub route: String,
+    pub event: Box<Event>,
+}
+impl From<RouteEvent> for Event {
+    fn from(value: RouteEvent) -> Self {
+        Event::Route(value)
+    }
 }
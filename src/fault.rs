@@ -0,0 +1,297 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    latency: Duration,
+    jitter: Duration,
+    bandwidth: Option<u64>,
+    reset_probability: f64,
+    truncate_after: Option<u64>,
+    connect_delay: Duration,
+    drop_probability: f64,
+    duplicate_probability: f64,
+    reorder_probability: f64,
+    corrupt_probability: f64,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.bandwidth = Some(bytes_per_second);
+        self
+    }
+
+    pub fn reset_probability(mut self, probability: f64) -> Self {
+        self.reset_probability = probability;
+        self
+    }
+
+    pub fn truncate_after(mut self, bytes: u64) -> Self {
+        self.truncate_after = Some(bytes);
+        self
+    }
+
+    pub fn connect_delay(mut self, delay: Duration) -> Self {
+        self.connect_delay = delay;
+        self
+    }
+
+    pub fn drop_probability(mut self, probability: f64) -> Self {
+        self.drop_probability = probability;
+        self
+    }
+
+    pub fn duplicate_probability(mut self, probability: f64) -> Self {
+        self.duplicate_probability = probability;
+        self
+    }
+
+    pub fn reorder_probability(mut self, probability: f64) -> Self {
+        self.reorder_probability = probability;
+        self
+    }
+
+    pub fn corrupt_probability(mut self, probability: f64) -> Self {
+        self.corrupt_probability = probability;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    faults: Faults,
+    enabled: bool,
+    rng: StdRng,
+}
+
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjector {
+    pub fn new(faults: Faults) -> Self {
+        Self::from_rng(faults, StdRng::from_entropy())
+    }
+
+    pub fn seeded(faults: Faults, seed: u64) -> Self {
+        Self::from_rng(faults, StdRng::seed_from_u64(seed))
+    }
+
+    fn from_rng(faults: Faults, rng: StdRng) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                faults,
+                enabled: true,
+                rng,
+            })),
+        }
+    }
+
+    pub fn faults(&self) -> Faults {
+        self.state.lock().unwrap().faults.clone()
+    }
+
+    pub fn set_faults(&self, faults: Faults) {
+        self.state.lock().unwrap().faults = faults;
+    }
+
+    pub fn reseed(&self, seed: u64) {
+        self.state.lock().unwrap().rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn enable(&self) {
+        self.state.lock().unwrap().enabled = true;
+    }
+
+    pub fn disable(&self) {
+        self.state.lock().unwrap().enabled = false;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().enabled
+    }
+
+    fn with_faults<T: Default>(&self, f: impl FnOnce(&Faults, &mut StdRng) -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        if !state.enabled {
+            return T::default();
+        }
+        let State { faults, rng, .. } = &mut *state;
+        f(faults, rng)
+    }
+
+    fn roll(&self, probability: impl FnOnce(&Faults) -> f64) -> bool {
+        self.with_faults(|faults, rng| {
+            let probability = probability(faults);
+            probability > 0.0 && rng.gen_bool(probability.min(1.0))
+        })
+    }
+
+    pub(crate) fn delay(&self, len: usize) -> Duration {
+        self.with_faults(|faults, rng| {
+            let mut delay = faults.latency;
+            if !faults.jitter.is_zero() {
+                let jitter = rng.gen_range(Duration::ZERO..=faults.jitter * 2);
+                delay = (delay + jitter).saturating_sub(faults.jitter);
+            }
+            if let Some(bandwidth) = faults.bandwidth.filter(|&b| b > 0) {
+                delay += Duration::from_secs_f64(len as f64 / bandwidth as f64);
+            }
+            delay
+        })
+    }
+
+    pub(crate) fn connect_delay(&self) -> Duration {
+        self.with_faults(|faults, _| faults.connect_delay)
+    }
+
+    pub(crate) fn truncate_after(&self) -> Option<u64> {
+        self.with_faults(|faults, _| faults.truncate_after)
+    }
+
+    pub(crate) fn should_reset(&self) -> bool {
+        self.roll(|faults| faults.reset_probability)
+    }
+
+    pub(crate) fn should_drop(&self) -> bool {
+        self.roll(|faults| faults.drop_probability)
+    }
+
+    pub(crate) fn should_duplicate(&self) -> bool {
+        self.roll(|faults| faults.duplicate_probability)
+    }
+
+    pub(crate) fn should_reorder(&self) -> bool {
+        self.roll(|faults| faults.reorder_probability)
+    }
+
+    pub(crate) fn corrupt(&self, datagram: &mut [u8]) {
+        if datagram.is_empty() || !self.roll(|faults| faults.corrupt_probability) {
+            return;
+        }
+        self.with_faults(|_, rng| {
+            let index = rng.gen_range(0..datagram.len());
+            datagram[index] ^= 1 << rng.gen_range(0..8);
+        });
+    }
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::seeded(Faults::default(), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rolls(injector: &FaultInjector) -> Vec<bool> {
+        (0..64).map(|_| injector.should_drop()).collect()
+    }
+
+    #[test]
+    fn same_seed_rolls_the_same_faults() {
+        let faults = Faults::new().drop_probability(0.5);
+        let first = rolls(&FaultInjector::seeded(faults.clone(), 7));
+        let second = rolls(&FaultInjector::seeded(faults.clone(), 7));
+        assert_eq!(first, second);
+        assert!(first.contains(&true) && first.contains(&false));
+
+        let injector = FaultInjector::seeded(faults, 8);
+        let other = rolls(&injector);
+        assert_ne!(first, other);
+        injector.reseed(7);
+        assert_eq!(rolls(&injector), first);
+    }
+
+    #[test]
+    fn certain_and_impossible_probabilities() {
+        let injector = FaultInjector::seeded(
+            Faults::new().drop_probability(1.0).reset_probability(2.0),
+            1,
+        );
+        assert!((0..32).all(|_| injector.should_drop() && injector.should_reset()));
+        assert!((0..32).all(|_| !injector.should_duplicate() && !injector.should_reorder()));
+    }
+
+    #[test]
+    fn delay_combines_latency_jitter_and_bandwidth() {
+        let injector = FaultInjector::seeded(
+            Faults::new()
+                .latency(Duration::from_millis(100))
+                .bandwidth(1000),
+            1,
+        );
+        assert_eq!(injector.delay(500), Duration::from_millis(600));
+
+        injector.set_faults(
+            Faults::new()
+                .latency(Duration::from_millis(100))
+                .jitter(Duration::from_millis(20)),
+        );
+        for _ in 0..64 {
+            let delay = injector.delay(0);
+            assert!(delay >= Duration::from_millis(80) && delay <= Duration::from_millis(120));
+        }
+    }
+
+    #[test]
+    fn corrupt_flips_exactly_one_bit() {
+        let injector = FaultInjector::seeded(Faults::new().corrupt_probability(1.0), 3);
+        let original = vec![0u8; 16];
+        let mut datagram = original.clone();
+        injector.corrupt(&mut datagram);
+        let flipped: u32 = original
+            .iter()
+            .zip(&datagram)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert_eq!(flipped, 1);
+    }
+
+    #[test]
+    fn disabled_injector_injects_nothing() {
+        let injector = FaultInjector::seeded(
+            Faults::new()
+                .latency(Duration::from_secs(1))
+                .connect_delay(Duration::from_secs(1))
+                .truncate_after(10)
+                .drop_probability(1.0)
+                .reset_probability(1.0)
+                .corrupt_probability(1.0),
+            1,
+        );
+        injector.disable();
+        assert!(!injector.is_enabled());
+        assert_eq!(injector.delay(100), Duration::ZERO);
+        assert_eq!(injector.connect_delay(), Duration::ZERO);
+        assert_eq!(injector.truncate_after(), None);
+        assert!(!injector.should_drop());
+        assert!(!injector.should_reset());
+        let mut datagram = vec![0u8; 4];
+        injector.corrupt(&mut datagram);
+        assert_eq!(datagram, [0; 4]);
+
+        injector.enable();
+        assert!(injector.should_drop());
+        assert_eq!(injector.truncate_after(), Some(10));
+    }
+}
@@ -0,0 +1,38 @@
+use crate::acl::AccessList;
+use crate::fault::FaultInjector;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone)]
+pub struct ProxyHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+    access_list: AccessList,
+    faults: FaultInjector,
+}
+
+impl ProxyHandle {
+    pub(crate) fn new(access_list: AccessList, faults: FaultInjector) -> Self {
+        Self {
+            shutdown: Arc::new(watch::channel(false).0),
+            access_list,
+            faults,
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn access_list(&self) -> AccessList {
+        self.access_list.clone()
+    }
+
+    pub fn faults(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+
+    pub(crate) async fn wait_for_shutdown(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+    }
+}
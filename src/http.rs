@@ -0,0 +1,708 @@
+use crate::acl::{AccessList, Cidr};
+use crate::address::Address;
+#[cfg(unix)]
+use crate::address::UnixAddress;
+use crate::error::Error;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{lookup_host, ToSocketAddrs};
+use tokio::task::JoinSet;
+
+const MAX_HEADERS: usize = 128;
+pub(crate) const MAX_HEAD: usize = 64 * 1024;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+#[derive(Debug)]
+pub struct HttpConfig {
+    routes_handles: JoinSet<io::Result<HttpRoute>>,
+    x_forwarded_for: bool,
+    forwarded: bool,
+    body_capture_limit: Option<usize>,
+}
+
+impl HttpConfig {
+    pub fn new() -> Self {
+        Self {
+            routes_handles: JoinSet::new(),
+            x_forwarded_for: false,
+            forwarded: false,
+            body_capture_limit: None,
+        }
+    }
+
+    pub fn route<A: ToSocketAddrs + Send + 'static>(
+        mut self,
+        host: impl Into<String>,
+        path_prefix: impl Into<String>,
+        remote_addrs: A,
+    ) -> Self {
+        let host = host.into().to_ascii_lowercase();
+        let path_prefix = path_prefix.into();
+        self.routes_handles.spawn(async move {
+            lookup_host(remote_addrs)
+                .await
+                .map(|lookup_host| HttpRoute {
+                    host,
+                    path_prefix,
+                    remote_addrs: lookup_host.map(Address::Inet).collect(),
+                })
+        });
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn unix_route(
+        mut self,
+        host: impl Into<String>,
+        path_prefix: impl Into<String>,
+        remote_addr: UnixAddress,
+    ) -> Self {
+        let host = host.into().to_ascii_lowercase();
+        let path_prefix = path_prefix.into();
+        self.routes_handles.spawn(async move {
+            Ok(HttpRoute {
+                host,
+                path_prefix,
+                remote_addrs: vec![Address::Unix(remote_addr)],
+            })
+        });
+        self
+    }
+
+    pub fn x_forwarded_for(mut self) -> Self {
+        self.x_forwarded_for = true;
+        self
+    }
+
+    pub fn forwarded(mut self) -> Self {
+        self.forwarded = true;
+        self
+    }
+
+    pub fn capture_bodies(mut self, limit: usize) -> Self {
+        self.body_capture_limit = Some(limit);
+        self
+    }
+
+    pub(crate) async fn build(mut self) -> Result<Http, Error> {
+        let routes = Error::resolve_all(&mut self.routes_handles).await?;
+        Ok(Http {
+            routes,
+            x_forwarded_for: self.x_forwarded_for,
+            forwarded: self.forwarded,
+            body_capture_limit: self.body_capture_limit,
+        })
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct HttpRoute {
+    host: String,
+    path_prefix: String,
+    remote_addrs: Vec<Address>,
+}
+
+fn host_rank(pattern: &str, host: Option<&str>) -> Option<usize> {
+    if pattern == "*" {
+        return Some(0);
+    }
+    let host = host?;
+    if pattern == host {
+        return Some(usize::MAX);
+    }
+    let suffix = pattern.strip_prefix('*')?;
+    (suffix.starts_with('.') && host.len() > suffix.len() && host.ends_with(suffix))
+        .then_some(suffix.len())
+}
+
+pub(crate) fn parse_authority(authority: &str) -> Option<(String, u16)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            (host, rest.strip_prefix(':')?)
+        }
+        None => authority.rsplit_once(':')?,
+    };
+    let port = port.parse().ok()?;
+    (!host.is_empty()).then(|| (host.to_ascii_lowercase(), port))
+}
+
+fn path_matches(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?']),
+        None => false,
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Http {
+    routes: Vec<HttpRoute>,
+    x_forwarded_for: bool,
+    forwarded: bool,
+    body_capture_limit: Option<usize>,
+}
+
+impl Http {
+    pub(crate) fn body_capture_limit(&self) -> Option<usize> {
+        self.body_capture_limit
+    }
+
+    pub(crate) fn route(&self, host: Option<&str>, path: &str) -> Option<(usize, &[Address])> {
+        self.routes
+            .iter()
+            .enumerate()
+            .filter(|(_, route)| path_matches(&route.path_prefix, path))
+            .filter_map(|(index, route)| {
+                let rank = host_rank(&route.host, host)?;
+                Some(((rank, route.path_prefix.len()), index, route))
+            })
+            .max_by_key(|(rank, _, _)| *rank)
+            .map(|(_, index, route)| (index, route.remote_addrs.as_slice()))
+    }
+
+    pub(crate) fn add_forwarding_headers(
+        &self,
+        head: &mut RequestHead,
+        client_addr: &Address,
+        secure: bool,
+    ) {
+        let client_ip = client_addr.ip().map(|ip| match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        });
+        if self.x_forwarded_for {
+            let client_ip = client_ip.map_or("unknown".to_owned(), |ip| ip.to_string());
+            let value = match head.header("x-forwarded-for") {
+                Some(existing) => format!("{}, {client_ip}", String::from_utf8_lossy(existing)),
+                None => client_ip,
+            };
+            head.set_header("X-Forwarded-For", value.into_bytes());
+        }
+        if self.forwarded {
+            let node = match client_ip {
+                Some(IpAddr::V4(ip)) => ip.to_string(),
+                Some(IpAddr::V6(ip)) => format!("\"[{ip}]\""),
+                None => "unknown".to_owned(),
+            };
+            let mut element = format!("for={node}");
+            if let Some(host) = head.header("host") {
+                element.push_str(&format!(";host=\"{}\"", String::from_utf8_lossy(host)));
+            }
+            element.push_str(if secure {
+                ";proto=https"
+            } else {
+                ";proto=http"
+            });
+            let value = match head.header("forwarded") {
+                Some(existing) => format!("{}, {element}", String::from_utf8_lossy(existing)),
+                None => element,
+            };
+            head.set_header("Forwarded", value.into_bytes());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectConfig {
+    hosts: Vec<String>,
+    ports: Vec<u16>,
+    networks: AccessList,
+}
+
+impl ConnectConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_host(mut self, pattern: impl Into<String>) -> Self {
+        self.hosts.push(pattern.into().to_ascii_lowercase());
+        self
+    }
+
+    pub fn allow_port(mut self, port: u16) -> Self {
+        self.ports.push(port);
+        self
+    }
+
+    pub fn allow_network(self, cidr: Cidr) -> Self {
+        self.networks.allow(cidr);
+        self
+    }
+
+    pub(crate) fn allows_target(&self, host: &str, port: u16) -> bool {
+        (self.hosts.is_empty()
+            || self
+                .hosts
+                .iter()
+                .any(|pattern| host_rank(pattern, Some(host)).is_some()))
+            && (self.ports.is_empty() || self.ports.contains(&port))
+    }
+
+    pub(crate) fn allows_addr(&self, addr: SocketAddr) -> bool {
+        self.networks.is_allowed(addr.ip())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Body {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+fn has_token(value: &[u8], token: &str) -> bool {
+    String::from_utf8_lossy(value)
+        .split(',')
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+fn header<'a>(headers: &'a [(String, Vec<u8>)], name: &str) -> Option<&'a [u8]> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_slice())
+}
+
+fn header_values<'a>(
+    headers: &'a [(String, Vec<u8>)],
+    name: &'a str,
+) -> impl Iterator<Item = &'a [u8]> {
+    headers
+        .iter()
+        .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_slice())
+}
+
+fn content_length(headers: &[(String, Vec<u8>)]) -> io::Result<Option<u64>> {
+    let mut length = None;
+    for value in header_values(headers, "content-length") {
+        let value =
+            std::str::from_utf8(value).map_err(|_| invalid_data("invalid Content-Length"))?;
+        for item in value.split(',') {
+            let item = item.trim();
+            if item.is_empty() || !item.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid_data("invalid Content-Length"));
+            }
+            let item: u64 = item
+                .parse()
+                .map_err(|_| invalid_data("invalid Content-Length"))?;
+            if length.is_some_and(|length| length != item) {
+                return Err(invalid_data("conflicting Content-Length"));
+            }
+            length = Some(item);
+        }
+    }
+    Ok(length)
+}
+
+fn is_chunked(headers: &[(String, Vec<u8>)]) -> io::Result<bool> {
+    let mut values = header_values(headers, "transfer-encoding");
+    let Some(value) = values.next() else {
+        return Ok(false);
+    };
+    if values.next().is_some() {
+        return Err(invalid_data("multiple Transfer-Encoding headers"));
+    }
+    let value = String::from_utf8_lossy(value);
+    let last = value.rsplit(',').next().unwrap_or_default().trim();
+    if !last.eq_ignore_ascii_case("chunked") {
+        return Err(invalid_data("Transfer-Encoding must end with chunked"));
+    }
+    Ok(true)
+}
+
+fn keep_alive(minor_version: u8, headers: &[(String, Vec<u8>)]) -> bool {
+    match header(headers, "connection") {
+        Some(value) if has_token(value, "close") => false,
+        Some(value) if has_token(value, "keep-alive") => true,
+        _ => minor_version >= 1,
+    }
+}
+
+// Framing headers are never dropped, even when the Connection header names
+// them, so the body is always forwarded the way it was parsed.
+fn strip_hop_by_hop(minor_version: u8, headers: &mut Vec<(String, Vec<u8>)>) {
+    let keep_alive = keep_alive(minor_version, headers);
+    let listed: Vec<String> = header_values(headers, "connection")
+        .flat_map(|value| {
+            String::from_utf8_lossy(value)
+                .split(',')
+                .map(|token| token.trim().to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        })
+        .filter(|token| {
+            !matches!(
+                token.as_str(),
+                "content-length" | "transfer-encoding" | "host"
+            )
+        })
+        .collect();
+    let upgrade =
+        listed.iter().any(|token| token == "upgrade") && header(headers, "upgrade").is_some();
+    headers.retain(|(name, _)| {
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "connection" | "keep-alive" | "te" => false,
+            "upgrade" => upgrade,
+            name if name.starts_with("proxy-") => false,
+            name => !listed.iter().any(|token| token == name),
+        }
+    });
+    let mut connection = Vec::new();
+    if upgrade {
+        connection.push("upgrade");
+    }
+    match (keep_alive, minor_version >= 1) {
+        (true, false) => connection.push("keep-alive"),
+        (false, true) => connection.push("close"),
+        _ => {}
+    }
+    if !connection.is_empty() {
+        headers.push(("Connection".to_owned(), connection.join(", ").into_bytes()));
+    }
+}
+
+pub(crate) fn parse_chunk_size(line: &[u8]) -> io::Result<u64> {
+    let line = String::from_utf8_lossy(line);
+    let size = line.split(';').next().unwrap_or_default().trim();
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid_data("invalid chunk size"));
+    }
+    u64::from_str_radix(size, 16).map_err(|_| invalid_data("invalid chunk size"))
+}
+
+fn collect_headers(headers: &[httparse::Header<'_>]) -> Vec<(String, Vec<u8>)> {
+    headers
+        .iter()
+        .map(|header| (header.name.to_owned(), header.value.to_vec()))
+        .collect()
+}
+
+fn encode_headers(head: &mut Vec<u8>, headers: &[(String, Vec<u8>)]) {
+    for (name, value) in headers {
+        head.extend_from_slice(name.as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value);
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RequestHead {
+    pub(crate) method: String,
+    pub(crate) target: String,
+    pub(crate) minor_version: u8,
+    pub(crate) headers: Vec<(String, Vec<u8>)>,
+}
+
+impl RequestHead {
+    pub(crate) fn parse(buffer: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(buffer) {
+            Ok(httparse::Status::Complete(len)) => {
+                let head = Self {
+                    method: request.method.unwrap_or_default().to_owned(),
+                    target: request.path.unwrap_or_default().to_owned(),
+                    minor_version: request.version.unwrap_or(1),
+                    headers: collect_headers(request.headers),
+                };
+                head.body()?;
+                Ok(Some((head, len)))
+            }
+            Ok(httparse::Status::Partial) => Ok(None),
+            Err(error) => Err(invalid_data(&error.to_string())),
+        }
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<&[u8]> {
+        header(&self.headers, name)
+    }
+
+    pub(crate) fn set_header(&mut self, name: &str, value: Vec<u8>) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_owned(), value));
+    }
+
+    fn authority(&self) -> Option<(&str, &str)> {
+        let rest = self
+            .target
+            .strip_prefix("http://")
+            .or_else(|| self.target.strip_prefix("https://"))?;
+        let end = rest.find(['/', '?']).unwrap_or(rest.len());
+        Some((&rest[..end], &rest[end..]))
+    }
+
+    pub(crate) fn host(&self) -> Option<String> {
+        let authority = match self.authority() {
+            Some((authority, _)) => authority.to_owned(),
+            None => String::from_utf8_lossy(self.header("host")?).into_owned(),
+        };
+        let authority = authority
+            .rsplit_once('@')
+            .map_or(&*authority, |(_, host)| host);
+        let host = match authority.strip_prefix('[') {
+            Some(rest) => rest.split(']').next().unwrap_or(rest),
+            None => authority.split(':').next().unwrap_or(authority),
+        };
+        Some(host.trim().to_ascii_lowercase()).filter(|host| !host.is_empty())
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        match self.authority() {
+            Some((_, "")) => "/",
+            Some((_, path)) => path,
+            None => &self.target,
+        }
+    }
+
+    pub(crate) fn use_origin_form(&mut self) {
+        if let Some((authority, path)) = self.authority() {
+            let authority = authority.to_owned();
+            self.target = if path.starts_with('/') {
+                path.to_owned()
+            } else {
+                format!("/{path}")
+            };
+            if self.header("host").is_none() {
+                self.headers
+                    .push(("Host".to_owned(), authority.into_bytes()));
+            }
+        }
+    }
+
+    pub(crate) fn body(&self) -> io::Result<Body> {
+        let chunked = is_chunked(&self.headers)?;
+        let length = content_length(&self.headers)?;
+        Ok(match (chunked, length) {
+            (true, Some(_)) => {
+                return Err(invalid_data(
+                    "both Transfer-Encoding and Content-Length are present",
+                ))
+            }
+            (true, None) => Body::Chunked,
+            (false, Some(0) | None) => Body::Empty,
+            (false, Some(len)) => Body::Length(len),
+        })
+    }
+
+    pub(crate) fn keep_alive(&self) -> bool {
+        keep_alive(self.minor_version, &self.headers)
+    }
+
+    pub(crate) fn strip_hop_by_hop(&mut self) {
+        strip_hop_by_hop(self.minor_version, &mut self.headers);
+    }
+
+    pub(crate) fn expects_continue(&self) -> bool {
+        self.header("expect")
+            .is_some_and(|value| has_token(value, "100-continue"))
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut head = format!(
+            "{} {} HTTP/1.{}\r\n",
+            self.method, self.target, self.minor_version
+        )
+        .into_bytes();
+        encode_headers(&mut head, &self.headers);
+        head
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ResponseHead {
+    pub(crate) status: u16,
+    pub(crate) reason: String,
+    pub(crate) minor_version: u8,
+    pub(crate) headers: Vec<(String, Vec<u8>)>,
+}
+
+impl ResponseHead {
+    pub(crate) fn parse(buffer: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut response = httparse::Response::new(&mut headers);
+        match response.parse(buffer) {
+            Ok(httparse::Status::Complete(len)) => Ok(Some((
+                Self {
+                    status: response.code.unwrap_or_default(),
+                    reason: response.reason.unwrap_or_default().to_owned(),
+                    minor_version: response.version.unwrap_or(1),
+                    headers: collect_headers(response.headers),
+                },
+                len,
+            ))),
+            Ok(httparse::Status::Partial) => Ok(None),
+            Err(error) => Err(invalid_data(&error.to_string())),
+        }
+    }
+
+    pub(crate) fn is_informational(&self) -> bool {
+        (100..200).contains(&self.status)
+    }
+
+    pub(crate) fn body(&self, method: &str) -> io::Result<Body> {
+        if method.eq_ignore_ascii_case("HEAD") || matches!(self.status, 100..=199 | 204 | 304) {
+            return Ok(Body::Empty);
+        }
+        if is_chunked(&self.headers)? {
+            return Ok(Body::Chunked);
+        }
+        Ok(match content_length(&self.headers)? {
+            Some(0) => Body::Empty,
+            Some(len) => Body::Length(len),
+            None => Body::UntilClose,
+        })
+    }
+
+    pub(crate) fn keep_alive(&self) -> bool {
+        keep_alive(self.minor_version, &self.headers)
+    }
+
+    pub(crate) fn strip_hop_by_hop(&mut self) {
+        strip_hop_by_hop(self.minor_version, &mut self.headers);
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.{} {} {}\r\n",
+            self.minor_version, self.status, self.reason
+        )
+        .into_bytes();
+        encode_headers(&mut head, &self.headers);
+        head
+    }
+}
+
+pub(crate) fn error_response(status: u16, reason: &str) -> Vec<u8> {
+    format!("HTTP/1.1 {status} {reason}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_request(head: &str) -> io::Result<RequestHead> {
+        let raw = format!("POST / HTTP/1.1\r\nHost: example.com\r\n{head}\r\n");
+        RequestHead::parse(raw.as_bytes()).map(|head| head.unwrap().0)
+    }
+
+    #[test]
+    fn content_length_body() {
+        let request = parse_request("Content-Length: 5\r\n").unwrap();
+        assert_eq!(request.body().unwrap(), Body::Length(5));
+    }
+
+    #[test]
+    fn chunked_body() {
+        let request = parse_request("Transfer-Encoding: gzip, chunked\r\n").unwrap();
+        assert_eq!(request.body().unwrap(), Body::Chunked);
+    }
+
+    #[test]
+    fn repeated_identical_content_length_is_accepted() {
+        let request = parse_request("Content-Length: 5\r\nContent-Length: 5\r\n").unwrap();
+        assert_eq!(request.body().unwrap(), Body::Length(5));
+    }
+
+    #[test]
+    fn transfer_encoding_with_content_length_is_rejected() {
+        let error =
+            parse_request("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn conflicting_content_length_is_rejected() {
+        for head in [
+            "Content-Length: 5\r\nContent-Length: 6\r\n",
+            "Content-Length: 5, 6\r\n",
+            "Content-Length: +5\r\n",
+        ] {
+            let error = parse_request(head).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{head:?}");
+        }
+    }
+
+    #[test]
+    fn ambiguous_transfer_encoding_is_rejected() {
+        for head in [
+            "Transfer-Encoding: identity\r\nTransfer-Encoding: chunked\r\n",
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+            "Transfer-Encoding: chunked, identity\r\n",
+            "Transfer-Encoding: xchunked\r\n",
+        ] {
+            let error = parse_request(head).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{head:?}");
+        }
+    }
+
+    #[test]
+    fn hop_by_hop_headers_are_stripped() {
+        let mut request = parse_request(
+            "Connection: keep-alive, X-Secret, Content-Length\r\nKeep-Alive: timeout=5\r\n\
+             TE: trailers\r\nProxy-Authorization: Basic abc\r\nX-Secret: 1\r\n\
+             Content-Length: 3\r\nX-Kept: 1\r\n",
+        )
+        .unwrap();
+        request.strip_hop_by_hop();
+        let names: Vec<&str> = request
+            .headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["Host", "Content-Length", "X-Kept"]);
+        assert!(request.keep_alive());
+        assert_eq!(request.body().unwrap(), Body::Length(3));
+    }
+
+    #[test]
+    fn hop_by_hop_stripping_keeps_connection_semantics() {
+        let mut request = parse_request("Connection: close\r\n").unwrap();
+        request.strip_hop_by_hop();
+        assert_eq!(request.header("connection"), Some(&b"close"[..]));
+
+        let raw = b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+        let (mut request, _) = RequestHead::parse(raw).unwrap().unwrap();
+        request.strip_hop_by_hop();
+        assert!(request.keep_alive());
+
+        let mut request = parse_request("Connection: Upgrade\r\nUpgrade: websocket\r\n").unwrap();
+        request.strip_hop_by_hop();
+        assert_eq!(request.header("upgrade"), Some(&b"websocket"[..]));
+        assert_eq!(request.header("connection"), Some(&b"upgrade"[..]));
+    }
+
+    #[test]
+    fn response_heads_are_reencoded() {
+        let raw = b"HTTP/1.1 200 OK\r\nConnection: X-Hop\r\nX-Hop: 1\r\nContent-Length: 0\r\n\r\n";
+        let (mut response, _) = ResponseHead::parse(raw).unwrap().unwrap();
+        response.strip_hop_by_hop();
+        assert_eq!(
+            response.encode(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn chunk_sizes_must_be_hex_digits() {
+        assert_eq!(parse_chunk_size(b"1a").unwrap(), 26);
+        assert_eq!(parse_chunk_size(b"A ; ext=1").unwrap(), 10);
+        for line in [&b""[..], b"+5", b"-5", b"0x5", b"5 5", b"g"] {
+            let error = parse_chunk_size(line).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{line:?}");
+        }
+    }
+}
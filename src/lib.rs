@@ -1,42 +1,143 @@
+pub mod acl;
+pub mod address;
+pub mod capture;
+pub mod chain;
+pub mod fault;
+pub mod http;
+pub mod limit;
+pub mod proxy_protocol;
+pub mod record;
 pub mod tcp;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tls;
+pub mod transport;
 pub mod udp;
 
 mod builder;
+mod direction;
+mod error;
 mod event;
+mod handle;
+mod net;
+mod route;
+mod sni;
+mod socks;
+mod writer;
 
+pub use acl::{AccessList, Cidr};
+pub use address::{Address, UnixAddress};
 pub use builder::ProxyBuilder;
+pub use capture::Capture;
+pub use chain::UpstreamProxy;
+pub use direction::Direction;
+pub use error::Error;
 pub use event::Event;
+pub use fault::{FaultInjector, Faults};
+pub use handle::ProxyHandle;
+pub use http::{ConnectConfig, HttpConfig};
+pub use limit::RateLimit;
+pub use proxy_protocol::{ProxyProtocol, ProxyProtocolMode};
+use record::Protocol;
+pub use record::{Recorder, Recording, ReplayMode};
+use route::Route;
+pub use socks::Socks5Config;
+use std::io;
+pub use tls::{ClientIdentity, TlsClientConfig, TlsServerConfig};
 use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
 
 #[derive(Debug)]
 struct ProxyEventManager {
     event_sender: mpsc::Sender<Event>,
     tcp_event_receiver: mpsc::Receiver<tcp::Event>,
     udp_event_receiver: mpsc::Receiver<udp::Event>,
+    route_forwards: Vec<JoinHandle<()>>,
 }
 
 impl ProxyEventManager {
-    pub async fn run(mut self, mut close_rx: mpsc::Receiver<()>) {
-        loop {
-            tokio::select! {
-                Some(event) = self.tcp_event_receiver.recv() => {
+    async fn forward(&mut self) {
+        tokio::select! {
+            Some(event) = self.tcp_event_receiver.recv() => {
+                let _ = self.event_sender.send(Event::Tcp(event)).await;
+            }
+            Some(event) = self.udp_event_receiver.recv() => {
+                let _ = self.event_sender.send(Event::Udp(event)).await;
+            }
+            else => std::future::pending().await,
+        }
+    }
+
+    // Forwards whatever a stopped side still has queued; its channel closes
+    // once the side and its connection tasks have been dropped.
+    async fn drain(&mut self, side: Side) {
+        match side {
+            Side::Tcp => {
+                while let Some(event) = self.tcp_event_receiver.recv().await {
                     let _ = self.event_sender.send(Event::Tcp(event)).await;
                 }
-                Some(event) = self.udp_event_receiver.recv() => {
+            }
+            Side::Udp => {
+                while let Some(event) = self.udp_event_receiver.recv().await {
                     let _ = self.event_sender.send(Event::Udp(event)).await;
                 }
-                _ = close_rx.recv() => {
-                    break;
-                }
+            }
+            Side::Route(index) => {
+                let _ = (&mut self.route_forwards[index]).await;
             }
         }
     }
+
+    async fn stopped(
+        &mut self,
+        side: Side,
+        route: Option<&str>,
+        protocol: Protocol,
+        result: &io::Result<()>,
+    ) {
+        self.drain(side).await;
+        let result = match result {
+            Ok(()) => Ok(()),
+            Err(error) => Err(io::Error::new(error.kind(), error.to_string())),
+        };
+        let mut event = Event::from(event::Stopped { protocol, result });
+        if let Some(route) = route {
+            event = Event::from(event::RouteEvent {
+                route: route.to_owned(),
+                event: Box::new(event),
+            });
+        }
+        let _ = self.event_sender.send(event).await;
+    }
+}
+
+async fn forward_events(event_manager: Option<&mut ProxyEventManager>) {
+    match event_manager {
+        Some(event_manager) => event_manager.forward().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Tcp,
+    Udp,
+    Route(usize),
+}
+
+#[derive(Debug)]
+pub struct Exit {
+    pub tcp: Option<io::Result<()>>,
+    pub udp: Option<io::Result<()>>,
+    pub routes: Vec<(String, io::Result<()>)>,
 }
 
 #[derive(Debug)]
 pub struct Proxy {
-    tcp: tcp::Proxy,
-    udp: udp::Proxy,
+    tcp: Option<tcp::Proxy>,
+    udp: Option<udp::Proxy>,
+    routes: Vec<(String, Route)>,
+    handle: ProxyHandle,
     event_manager: Option<ProxyEventManager>,
 }
 
@@ -45,20 +146,99 @@ impl Proxy {
         ProxyBuilder::new()
     }
 
-    pub async fn run(self) {
-        let (close_tx, close_rx) = mpsc::channel(1);
+    pub fn access_list(&self) -> AccessList {
+        self.handle.access_list()
+    }
+
+    pub fn faults(&self) -> FaultInjector {
+        self.handle.faults()
+    }
+
+    pub fn handle(&self) -> ProxyHandle {
+        self.handle.clone()
+    }
+
+    pub fn tcp_local_addr(&self) -> Option<Address> {
+        self.tcp.as_ref().map(tcp::Proxy::local_addr)
+    }
+
+    pub fn udp_local_addr(&self) -> Option<Address> {
+        self.udp.as_ref().map(udp::Proxy::local_addr)
+    }
+
+    pub fn route_local_addr(&self, name: &str) -> Option<Address> {
+        self.routes
+            .iter()
+            .find(|(route, _)| route == name)
+            .map(|(_, route)| route.local_addr())
+    }
 
-        if let Some(event_manager) = self.event_manager {
-            tokio::spawn(event_manager.run(close_rx));
+    pub async fn run(self) -> Exit {
+        let mut event_manager = self.event_manager;
+        let mut exit = Exit {
+            tcp: self.tcp.as_ref().map(|_| Ok(())),
+            udp: self.udp.as_ref().map(|_| Ok(())),
+            routes: Vec::with_capacity(self.routes.len()),
+        };
+        let mut running = Vec::new();
+        let mut sides = JoinSet::new();
+        if let Some(tcp) = self.tcp {
+            running.push((Side::Tcp, None, Protocol::Tcp));
+            sides.spawn(async move { (Side::Tcp, tcp.run().await) });
+        }
+        if let Some(udp) = self.udp {
+            running.push((Side::Udp, None, Protocol::Udp));
+            sides.spawn(async move { (Side::Udp, udp.run().await) });
+        }
+        for (index, (name, route)) in self.routes.into_iter().enumerate() {
+            running.push((Side::Route(index), Some(name.clone()), route.protocol()));
+            exit.routes.push((name, Ok(())));
+            sides.spawn(async move { (Side::Route(index), route.run().await) });
         }
 
-        let tcp = self.tcp.run();
-        let udp = self.udp.run();
-        tokio::select! {
-            _ = tcp => {}
-            _ = udp => {}
+        let mut shutting_down = false;
+        loop {
+            let joined = tokio::select! {
+                joined = sides.join_next() => joined,
+                _ = self.handle.wait_for_shutdown(), if !shutting_down => {
+                    sides.abort_all();
+                    shutting_down = true;
+                    continue;
+                }
+                _ = forward_events(event_manager.as_mut()) => continue,
+            };
+            let Some(joined) = joined else {
+                break;
+            };
+            let (side, result) = match joined {
+                Ok(joined) => joined,
+                Err(error) if error.is_cancelled() => continue,
+                Err(error) => std::panic::resume_unwind(error.into_panic()),
+            };
+            let Some(position) = running.iter().position(|(running, _, _)| *running == side) else {
+                continue;
+            };
+            let (_, route, protocol) = running.remove(position);
+            if let Some(event_manager) = &mut event_manager {
+                event_manager
+                    .stopped(side, route.as_deref(), protocol, &result)
+                    .await;
+            }
+            match side {
+                Side::Tcp => exit.tcp = Some(result),
+                Side::Udp => exit.udp = Some(result),
+                Side::Route(index) => exit.routes[index].1 = result,
+            }
+        }
+        // Sides aborted on shutdown have all been joined by now.
+        if let Some(event_manager) = &mut event_manager {
+            for (side, route, protocol) in running {
+                event_manager
+                    .stopped(side, route.as_deref(), protocol, &Ok(()))
+                    .await;
+            }
         }
 
-        let _ = close_tx.send(()).await;
+        exit
     }
 }
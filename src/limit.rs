@@ -0,0 +1,318 @@
+use crate::error::Error;
+use crate::Direction;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+const MIN_SWEEP_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    rate: u64,
+    burst: u64,
+}
+
+impl RateLimit {
+    pub fn new(rate: u64, burst: u64) -> Self {
+        Self {
+            rate,
+            burst: burst.max(1),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn burst(&self) -> u64 {
+        self.burst
+    }
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(BucketState {
+                tokens: limit.burst as f64,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(&self, state: &mut BucketState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.updated_at).as_secs_f64();
+        state.tokens =
+            (state.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        state.updated_at = now;
+    }
+
+    pub(crate) fn try_acquire(&self, amount: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state);
+        if state.tokens < amount as f64 {
+            return false;
+        }
+        state.tokens -= amount as f64;
+        true
+    }
+
+    fn refund(&self, amount: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.tokens = (state.tokens + amount as f64).min(self.limit.burst as f64);
+    }
+
+    pub(crate) async fn acquire(&self, mut amount: u64) {
+        while amount > 0 {
+            let chunk = amount.min(self.limit.burst);
+            loop {
+                let wait = {
+                    let mut state = self.state.lock().unwrap();
+                    self.refill(&mut state);
+                    if state.tokens >= chunk as f64 {
+                        state.tokens -= chunk as f64;
+                        break;
+                    }
+                    let missing = chunk as f64 - state.tokens;
+                    Duration::from_secs_f64(missing / self.limit.rate as f64)
+                };
+                sleep(wait).await;
+            }
+            amount -= chunk;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state);
+        state.tokens >= self.limit.burst as f64
+    }
+}
+
+pub(crate) fn try_acquire_all<'a>(
+    requests: impl IntoIterator<Item = (&'a TokenBucket, u64)>,
+) -> bool {
+    let mut acquired: Vec<(&TokenBucket, u64)> = Vec::new();
+    for (bucket, amount) in requests {
+        if !bucket.try_acquire(amount) {
+            for (bucket, amount) in acquired {
+                bucket.refund(amount);
+            }
+            return false;
+        }
+        acquired.push((bucket, amount));
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Limits {
+    upload: Option<RateLimit>,
+    download: Option<RateLimit>,
+}
+
+impl Limits {
+    pub(crate) fn set(&mut self, direction: Direction, limit: RateLimit) {
+        match direction {
+            Direction::Upload => self.upload = Some(limit),
+            Direction::Download => self.download = Some(limit),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.upload.is_none() && self.download.is_none()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if [self.upload, self.download]
+            .into_iter()
+            .flatten()
+            .any(|limit| limit.rate == 0)
+        {
+            return Err(Error::InvalidConfig(
+                "rate limits must have a non-zero rate",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Buckets {
+    upload: Option<TokenBucket>,
+    download: Option<TokenBucket>,
+}
+
+impl Buckets {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            upload: limits.upload.map(TokenBucket::new),
+            download: limits.download.map(TokenBucket::new),
+        }
+    }
+
+    pub(crate) fn get(&self, direction: Direction) -> Option<&TokenBucket> {
+        match direction {
+            Direction::Upload => self.upload.as_ref(),
+            Direction::Download => self.download.as_ref(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.upload.as_ref().is_none_or(TokenBucket::is_full)
+            && self.download.as_ref().is_none_or(TokenBucket::is_full)
+    }
+}
+
+#[derive(Debug)]
+struct KeyedState<K> {
+    buckets: HashMap<K, Arc<Buckets>>,
+    swept_at: Instant,
+    swept_len: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct KeyedBuckets<K> {
+    limits: Limits,
+    state: Mutex<KeyedState<K>>,
+}
+
+impl<K: Eq + Hash> KeyedBuckets<K> {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new(KeyedState {
+                buckets: HashMap::new(),
+                swept_at: Instant::now(),
+                swept_len: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn get(&self, key: K) -> Option<Arc<Buckets>> {
+        if self.limits.is_empty() {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        // Sweep periodically, and whenever the map has doubled since the last
+        // sweep, so the cost stays amortized and idle clients do not pile up.
+        if state.swept_at.elapsed() >= SWEEP_INTERVAL
+            || state.buckets.len() >= (2 * state.swept_len).max(MIN_SWEEP_LEN)
+        {
+            Self::sweep(&mut state);
+        }
+        let buckets = state
+            .buckets
+            .entry(key)
+            .or_insert_with(|| Arc::new(Buckets::new(self.limits)));
+        Some(buckets.clone())
+    }
+
+    fn sweep(state: &mut KeyedState<K>) {
+        // A full bucket behaves exactly like a fresh one, so idle entries can go.
+        state
+            .buckets
+            .retain(|_, b| Arc::strong_count(b) > 1 || !b.is_full());
+        state.swept_at = Instant::now();
+        state.swept_len = state.buckets.len();
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.state.lock().unwrap().buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_acquire_all_refunds_earlier_buckets() {
+        let roomy = TokenBucket::new(RateLimit::new(0, 100));
+        let tight = TokenBucket::new(RateLimit::new(0, 10));
+        assert!(!try_acquire_all([(&roomy, 50), (&tight, 50)]));
+        assert!(roomy.try_acquire(100));
+        assert!(tight.try_acquire(10));
+    }
+
+    #[test]
+    fn accepted_acquire_all_spends_every_bucket() {
+        let first = TokenBucket::new(RateLimit::new(0, 100));
+        let second = TokenBucket::new(RateLimit::new(0, 100));
+        assert!(try_acquire_all([(&first, 60), (&second, 30)]));
+        assert!(!first.try_acquire(50));
+        assert!(second.try_acquire(70));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_refill_at_the_configured_rate() {
+        let bucket = TokenBucket::new(RateLimit::new(10, 10));
+        assert!(bucket.try_acquire(10));
+        assert!(!bucket.try_acquire(1));
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(bucket.try_acquire(1));
+        assert!(!bucket.try_acquire(1));
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(bucket.try_acquire(5));
+        assert!(!bucket.try_acquire(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_is_capped_at_burst() {
+        let bucket = TokenBucket::new(RateLimit::new(10, 5));
+        assert!(bucket.try_acquire(5));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!bucket.try_acquire(6));
+        assert!(bucket.try_acquire(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_waits_for_missing_tokens() {
+        let bucket = TokenBucket::new(RateLimit::new(10, 10));
+        let started_at = Instant::now();
+        bucket.acquire(25).await;
+        assert_eq!(started_at.elapsed(), Duration::from_millis(1500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_keyed_buckets_are_evicted() {
+        let mut limits = Limits::default();
+        limits.set(Direction::Upload, RateLimit::new(10, 10));
+        let keyed = KeyedBuckets::new(limits);
+        for key in 0..MIN_SWEEP_LEN {
+            let buckets = keyed.get(key).unwrap();
+            assert!(buckets.get(Direction::Upload).unwrap().try_acquire(1));
+        }
+        assert_eq!(keyed.len(), MIN_SWEEP_LEN);
+
+        let held = keyed.get(MIN_SWEEP_LEN).unwrap();
+        assert!(held.get(Direction::Upload).unwrap().try_acquire(1));
+        tokio::time::advance(SWEEP_INTERVAL).await;
+        keyed.get(MIN_SWEEP_LEN + 1).unwrap();
+        assert_eq!(keyed.len(), 2);
+    }
+
+    #[test]
+    fn zero_rate_is_rejected() {
+        let mut limits = Limits::default();
+        limits.set(Direction::Download, RateLimit::new(0, 10));
+        assert!(matches!(limits.validate(), Err(Error::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,452 @@
+use crate::address::Address;
+#[cfg(unix)]
+use crate::address::UnixAddress;
+use crate::transport::{self, Connection, Connector};
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncWrite, Interest, ReadBuf};
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use {
+    std::fs,
+    std::os::unix::fs::FileTypeExt,
+    std::os::unix::net as std_unix,
+    std::path::{Path, PathBuf},
+    tokio::net::{UnixDatagram, UnixListener, UnixStream},
+};
+
+// Waits until the peer has sent more than the `peeked` bytes already seen.
+// The peek runs through `try_io`, so readiness is only cleared when no new
+// bytes have arrived since it was observed.
+pub(crate) async fn wait_for_more(stream: &TcpStream, peeked: usize) -> io::Result<()> {
+    let mut buffer = vec![MaybeUninit::<u8>::uninit(); peeked + 1];
+    loop {
+        let ready = stream.ready(Interest::READABLE).await?;
+        let result = stream.try_io(Interest::READABLE, || {
+            match socket2::SockRef::from(stream).peek(&mut buffer)? {
+                n if n > peeked => Ok(()),
+                _ if ready.is_read_closed() => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "peer closed before sending a complete header",
+                )),
+                _ => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        });
+        match result {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+pub(crate) async fn with_timeout<T>(
+    duration: Duration,
+    future: impl std::future::Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    tokio::time::timeout(duration, future)
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "handshake timed out",
+            ))
+        })
+}
+
+pub(crate) async fn resolve(
+    connector: Option<&dyn Connector>,
+    host: &str,
+    port: u16,
+) -> io::Result<Vec<SocketAddr>> {
+    match connector {
+        Some(connector) => connector.resolve(host, port).await,
+        None => Ok(lookup_host((host, port)).await?.collect()),
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct SocketFile(Option<PathBuf>);
+
+#[cfg(unix)]
+impl SocketFile {
+    fn remove_stale(
+        local_addr: &UnixAddress,
+        in_use: impl FnOnce(&Path) -> bool,
+    ) -> io::Result<()> {
+        let UnixAddress::Pathname(path) = local_addr else {
+            return Ok(());
+        };
+        let is_socket =
+            fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+        if is_socket {
+            if in_use(path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn bound(local_addr: &UnixAddress) -> Self {
+        match local_addr {
+            UnixAddress::Pathname(path) => SocketFile(Some(path.clone())),
+            _ => SocketFile(None),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        local_addr: UnixAddress,
+        _file: SocketFile,
+    },
+    Custom(Box<dyn transport::Listener>),
+}
+
+impl Listener {
+    pub(crate) async fn bind_tcp(local_addrs: &[SocketAddr]) -> io::Result<Self> {
+        TcpListener::bind(local_addrs).await.map(Listener::Tcp)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(local_addr: &UnixAddress) -> io::Result<Self> {
+        SocketFile::remove_stale(local_addr, |path| {
+            std_unix::UnixStream::connect(path).is_ok()
+        })?;
+        let listener = std_unix::UnixListener::bind_addr(&local_addr.to_std()?)?;
+        let file = SocketFile::bound(local_addr);
+        listener.set_nonblocking(true)?;
+        let local_addr = UnixAddress::from_std(&listener.local_addr()?);
+        Ok(Listener::Unix {
+            listener: UnixListener::from_std(listener)?,
+            local_addr,
+            _file: file,
+        })
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<(Socket, Address)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok((Socket::Tcp(stream), Address::Inet(peer_addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let (stream, _) = listener.accept().await?;
+                let socket = Socket::unix(stream)?;
+                let peer_addr = socket.peer_addr()?;
+                Ok((socket, peer_addr))
+            }
+            Listener::Custom(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok((Socket::Custom(stream), peer_addr))
+            }
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::Inet),
+            #[cfg(unix)]
+            Listener::Unix { local_addr, .. } => Ok(Address::Unix(local_addr.clone())),
+            Listener::Custom(listener) => listener.local_addr(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix {
+        stream: UnixStream,
+        local_addr: UnixAddress,
+        peer_addr: UnixAddress,
+    },
+    Custom(Box<dyn Connection>),
+}
+
+impl Socket {
+    #[cfg(unix)]
+    fn unix(stream: UnixStream) -> io::Result<Self> {
+        let stream = stream.into_std()?;
+        let local_addr = UnixAddress::from_std(&stream.local_addr()?);
+        let peer_addr = UnixAddress::from_std(&stream.peer_addr()?);
+        Ok(Socket::Unix {
+            stream: UnixStream::from_std(stream)?,
+            local_addr,
+            peer_addr,
+        })
+    }
+
+    pub(crate) async fn connect(remote_addr: &Address) -> io::Result<Self> {
+        match remote_addr {
+            Address::Inet(addr) => TcpStream::connect(addr).await.map(Socket::Tcp),
+            #[cfg(unix)]
+            Address::Unix(UnixAddress::Pathname(path)) => {
+                Socket::unix(UnixStream::connect(path).await?)
+            }
+            #[cfg(unix)]
+            Address::Unix(addr) => {
+                let addr = addr.to_std()?;
+                let stream =
+                    tokio::task::spawn_blocking(move || std_unix::UnixStream::connect_addr(&addr))
+                        .await??;
+                stream.set_nonblocking(true)?;
+                Socket::unix(UnixStream::from_std(stream)?)
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    pub(crate) fn tcp(&self) -> Option<&TcpStream> {
+        match self {
+            Socket::Tcp(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn tcp_mut(&mut self) -> Option<&mut TcpStream> {
+        match self {
+            Socket::Tcp(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Socket::Tcp(stream) => stream.local_addr().map(Address::Inet),
+            #[cfg(unix)]
+            Socket::Unix { local_addr, .. } => Ok(Address::Unix(local_addr.clone())),
+            Socket::Custom(stream) => stream.local_addr(),
+        }
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn peer_addr(&self) -> io::Result<Address> {
+        match self {
+            Socket::Tcp(stream) => stream.peer_addr().map(Address::Inet),
+            #[cfg(unix)]
+            Socket::Unix { peer_addr, .. } => Ok(Address::Unix(peer_addr.clone())),
+            Socket::Custom(stream) => stream.peer_addr(),
+        }
+    }
+
+    pub(crate) fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => socket2::SockRef::from(stream).set_linger(linger),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix { stream, .. } => Pin::new(stream).poll_read(cx, buf),
+            Socket::Custom(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Socket::Unix { stream, .. } => Pin::new(stream).poll_write(cx, buf),
+            Socket::Custom(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Socket::Unix { stream, .. } => Pin::new(stream).poll_flush(cx),
+            Socket::Custom(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Socket::Unix { stream, .. } => Pin::new(stream).poll_shutdown(cx),
+            Socket::Custom(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum DatagramSocket {
+    Udp(UdpSocket),
+    // `socket` drives readiness and `std` is a duplicate of the same socket
+    // used for abstract addresses, which tokio's datagram API cannot express.
+    #[cfg(unix)]
+    Unix {
+        socket: UnixDatagram,
+        std: std_unix::UnixDatagram,
+        _file: SocketFile,
+    },
+    Custom(Box<dyn transport::DatagramSocket>),
+}
+
+impl DatagramSocket {
+    pub(crate) async fn bind_udp(local_addrs: &[SocketAddr]) -> io::Result<Self> {
+        UdpSocket::bind(local_addrs).await.map(DatagramSocket::Udp)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(local_addr: &UnixAddress) -> io::Result<Self> {
+        SocketFile::remove_stale(local_addr, |path| {
+            std_unix::UnixDatagram::unbound().is_ok_and(|socket| socket.connect(path).is_ok())
+        })?;
+        let socket = std_unix::UnixDatagram::bind_addr(&local_addr.to_std()?)?;
+        let file = SocketFile::bound(local_addr);
+        socket.set_nonblocking(true)?;
+        Ok(DatagramSocket::Unix {
+            std: socket.try_clone()?,
+            socket: UnixDatagram::from_std(socket)?,
+            _file: file,
+        })
+    }
+
+    pub(crate) async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Address)> {
+        match self {
+            DatagramSocket::Udp(socket) => socket
+                .recv_from(buf)
+                .await
+                .map(|(len, addr)| (len, Address::Inet(addr))),
+            #[cfg(unix)]
+            DatagramSocket::Unix { socket, std, .. } => {
+                let (len, addr) = socket
+                    .async_io(Interest::READABLE, || std.recv_from(buf))
+                    .await?;
+                Ok((len, Address::Unix(UnixAddress::from_std(&addr))))
+            }
+            DatagramSocket::Custom(socket) => socket.recv_from(buf).await,
+        }
+    }
+
+    pub(crate) async fn send_to(&self, buf: &[u8], target: &Address) -> io::Result<usize> {
+        match (self, target) {
+            (DatagramSocket::Custom(socket), target) => socket.send_to(buf, target).await,
+            (DatagramSocket::Udp(socket), Address::Inet(addr)) => socket.send_to(buf, addr).await,
+            #[cfg(unix)]
+            (DatagramSocket::Unix { socket, std, .. }, Address::Unix(addr)) => {
+                let addr = addr.to_std()?;
+                socket
+                    .async_io(Interest::WRITABLE, || std.send_to_addr(buf, &addr))
+                    .await
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram target does not match the socket family",
+            )),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            DatagramSocket::Udp(socket) => socket.local_addr().map(Address::Inet),
+            #[cfg(unix)]
+            DatagramSocket::Unix { std, .. } => std
+                .local_addr()
+                .map(|addr| Address::Unix(UnixAddress::from_std(&addr))),
+            DatagramSocket::Custom(socket) => socket.local_addr(),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets are not supported on this platform",
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("proxy-rs-{}-{name}.sock", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn unix_listener_unlinks_on_drop_and_rebinds() {
+        let path = socket_path("listener");
+        let local_addr = UnixAddress::pathname(&path);
+        let listener = Listener::bind_unix(&local_addr).unwrap();
+        assert!(path.exists());
+        assert_eq!(
+            Listener::bind_unix(&local_addr).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+        drop(listener);
+        assert!(!path.exists());
+        drop(Listener::bind_unix(&local_addr).unwrap());
+    }
+
+    #[tokio::test]
+    async fn stale_unix_socket_is_replaced() {
+        let path = socket_path("stale");
+        drop(std_unix::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let listener = Listener::bind_unix(&UnixAddress::pathname(&path)).unwrap();
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn regular_file_is_not_removed() {
+        let path = socket_path("regular");
+        fs::write(&path, b"keep").unwrap();
+        assert!(Listener::bind_unix(&UnixAddress::pathname(&path)).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"keep");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unix_datagram_socket_unlinks_on_drop() {
+        let path = socket_path("datagram");
+        let local_addr = UnixAddress::pathname(&path);
+        drop(DatagramSocket::bind_unix(&local_addr).unwrap());
+        assert!(!path.exists());
+        drop(DatagramSocket::bind_unix(&local_addr).unwrap());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,518 @@
+use crate::error::Error;
+use crate::net;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt};
+use tokio::net::TcpStream;
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// The v2 length field covers addresses (at most 36 bytes for IPv6) and TLVs.
+const MAX_TLVS_LEN: usize = u16::MAX as usize - 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Stream,
+    Datagram,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyProtocol {
+    version: Version,
+    tlvs: Vec<Tlv>,
+}
+
+impl ProxyProtocol {
+    pub fn v1() -> Self {
+        Self {
+            version: Version::V1,
+            tlvs: Vec::new(),
+        }
+    }
+
+    pub fn v2() -> Self {
+        Self {
+            version: Version::V2,
+            tlvs: Vec::new(),
+        }
+    }
+
+    pub fn tlv(mut self, kind: u8, value: impl Into<Vec<u8>>) -> Self {
+        self.tlvs.push(Tlv {
+            kind,
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn tlvs(&self) -> &[Tlv] {
+        &self.tlvs
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.version == Version::V1 && !self.tlvs.is_empty() {
+            return Err(Error::InvalidConfig(
+                "PROXY protocol v1 does not support TLVs",
+            ));
+        }
+        let tlvs_len: usize = self.tlvs.iter().map(|tlv| 3 + tlv.value.len()).sum();
+        if tlvs_len > MAX_TLVS_LEN {
+            return Err(Error::InvalidConfig(
+                "PROXY protocol TLVs do not fit in a v2 header",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn encode(
+        &self,
+        transport: Transport,
+        source: SocketAddr,
+        destination: SocketAddr,
+    ) -> Vec<u8> {
+        match self.version {
+            Version::V1 => encode_v1(source, destination),
+            Version::V2 => encode_v2(transport, source, destination, &self.tlvs),
+        }
+    }
+
+    pub fn encode_local(&self) -> Vec<u8> {
+        match self.version {
+            Version::V1 => b"PROXY UNKNOWN\r\n".to_vec(),
+            Version::V2 => encode_v2_local(&self.tlvs),
+        }
+    }
+}
+
+fn normalize(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let (source, destination) = (normalize(source), normalize(destination));
+    if source.is_ipv4() == destination.is_ipv4() {
+        (source, destination)
+    } else {
+        (to_ipv6(source), to_ipv6(destination))
+    }
+}
+
+fn octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+pub fn encode_v1(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let (source, destination) = same_family(source, destination);
+    let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+    format!(
+        "PROXY {family} {} {} {} {}\r\n",
+        source.ip(),
+        destination.ip(),
+        source.port(),
+        destination.port()
+    )
+    .into_bytes()
+}
+
+// Builders reject TLVs that overflow these fields; anything else calling the
+// public encoders with oversized TLVs is a bug, not a recoverable condition.
+fn encode_len(len: usize) -> [u8; 2] {
+    u16::try_from(len)
+        .expect("PROXY protocol v2 header longer than 65535 bytes")
+        .to_be_bytes()
+}
+
+fn encode_tlvs(tlvs: &[Tlv], out: &mut Vec<u8>) {
+    for tlv in tlvs {
+        out.push(tlv.kind);
+        out.extend_from_slice(&encode_len(tlv.value.len()));
+        out.extend_from_slice(&tlv.value);
+    }
+}
+
+pub fn encode_v2(
+    transport: Transport,
+    source: SocketAddr,
+    destination: SocketAddr,
+    tlvs: &[Tlv],
+) -> Vec<u8> {
+    let (source, destination) = same_family(source, destination);
+    let family = if source.is_ipv4() { 0x10 } else { 0x20 };
+    let mut addresses = Vec::new();
+    addresses.extend_from_slice(&octets(source.ip()));
+    addresses.extend_from_slice(&octets(destination.ip()));
+    addresses.extend_from_slice(&source.port().to_be_bytes());
+    addresses.extend_from_slice(&destination.port().to_be_bytes());
+    encode_tlvs(tlvs, &mut addresses);
+    let transport = match transport {
+        Transport::Stream => 0x01,
+        Transport::Datagram => 0x02,
+    };
+
+    let mut header = Vec::with_capacity(16 + addresses.len());
+    header.extend_from_slice(&SIGNATURE);
+    header.push(0x21);
+    header.push(family | transport);
+    header.extend_from_slice(&encode_len(addresses.len()));
+    header.extend_from_slice(&addresses);
+    header
+}
+
+pub fn encode_v2_local(tlvs: &[Tlv]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    encode_tlvs(tlvs, &mut payload);
+
+    let mut header = Vec::with_capacity(16 + payload.len());
+    header.extend_from_slice(&SIGNATURE);
+    header.push(0x20);
+    header.push(0x00);
+    header.extend_from_slice(&encode_len(payload.len()));
+    header.extend_from_slice(&payload);
+    header
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocolMode {
+    Required,
+    Optional,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: Version,
+    pub transport: Option<Transport>,
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+    pub tlvs: Vec<Tlv>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+pub fn starts_like_header(buffer: &[u8]) -> bool {
+    let v1 = b"PROXY ";
+    let len = buffer.len();
+    buffer[..len.min(v1.len())] == v1[..len.min(v1.len())]
+        || buffer[..len.min(SIGNATURE.len())] == SIGNATURE[..len.min(SIGNATURE.len())]
+}
+
+pub fn decode(buffer: &[u8]) -> io::Result<Option<(Header, usize)>> {
+    if buffer.starts_with(b"PROXY ") {
+        let Some(end) = buffer.windows(2).position(|w| w == b"\r\n") else {
+            return if buffer.len() >= 107 {
+                Err(invalid_data("PROXY v1 header too long"))
+            } else {
+                Ok(None)
+            };
+        };
+        decode_v1(&buffer[..end]).map(|header| Some((header, end + 2)))
+    } else if buffer.starts_with(&SIGNATURE) {
+        if buffer.len() < 16 {
+            return Ok(None);
+        }
+        let len = 16 + usize::from(u16::from_be_bytes([buffer[14], buffer[15]]));
+        if buffer.len() < len {
+            return Ok(None);
+        }
+        decode_v2(&buffer[..len]).map(|header| Some((header, len)))
+    } else if starts_like_header(buffer) {
+        Ok(None)
+    } else {
+        Err(invalid_data("not a PROXY protocol header"))
+    }
+}
+
+fn decode_v1(line: &[u8]) -> io::Result<Header> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid_data("invalid PROXY v1 header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let parse_addr = |ip: &str, port: &str| -> io::Result<SocketAddr> {
+        let ip = ip
+            .parse()
+            .map_err(|_| invalid_data("invalid PROXY v1 address"))?;
+        let port = port
+            .parse()
+            .map_err(|_| invalid_data("invalid PROXY v1 port"))?;
+        Ok(SocketAddr::new(ip, port))
+    };
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(Header {
+            version: Version::V1,
+            transport: None,
+            source: None,
+            destination: None,
+            tlvs: Vec::new(),
+        }),
+        ["PROXY", "TCP4" | "TCP6", src, dst, sport, dport] => Ok(Header {
+            version: Version::V1,
+            transport: Some(Transport::Stream),
+            source: Some(parse_addr(src, sport)?),
+            destination: Some(parse_addr(dst, dport)?),
+            tlvs: Vec::new(),
+        }),
+        _ => Err(invalid_data("invalid PROXY v1 header")),
+    }
+}
+
+fn decode_v2(buffer: &[u8]) -> io::Result<Header> {
+    let version_command = buffer[12];
+    if version_command >> 4 != 2 {
+        return Err(invalid_data("unsupported PROXY protocol version"));
+    }
+    let mut header = Header {
+        version: Version::V2,
+        transport: None,
+        source: None,
+        destination: None,
+        tlvs: Vec::new(),
+    };
+    let command = version_command & 0x0f;
+    if command > 0x01 {
+        return Err(invalid_data("unsupported PROXY v2 command"));
+    }
+    let family = buffer[13] >> 4;
+    let transport = match buffer[13] & 0x0f {
+        0x01 => Some(Transport::Stream),
+        0x02 => Some(Transport::Datagram),
+        _ => None,
+    };
+    let payload = &buffer[16..];
+    let addresses_len = match (family, transport) {
+        (0x01, Some(_)) => 12,
+        (0x02, Some(_)) => 36,
+        (0x03, Some(_)) => 216,
+        _ => return Ok(header),
+    };
+    if payload.len() < addresses_len {
+        return Err(invalid_data("truncated PROXY v2 addresses"));
+    }
+    let addresses = &payload[..addresses_len];
+    if command == 0x01 {
+        let (src, dst, ports): (IpAddr, IpAddr, &[u8]) = match family {
+            0x01 => (
+                <[u8; 4]>::try_from(&addresses[0..4]).unwrap().into(),
+                <[u8; 4]>::try_from(&addresses[4..8]).unwrap().into(),
+                &addresses[8..12],
+            ),
+            0x02 => (
+                <[u8; 16]>::try_from(&addresses[0..16]).unwrap().into(),
+                <[u8; 16]>::try_from(&addresses[16..32]).unwrap().into(),
+                &addresses[32..36],
+            ),
+            _ => return Ok(header),
+        };
+        let sport = u16::from_be_bytes([ports[0], ports[1]]);
+        let dport = u16::from_be_bytes([ports[2], ports[3]]);
+        header.transport = transport;
+        header.source = Some(SocketAddr::new(src, sport));
+        header.destination = Some(SocketAddr::new(dst, dport));
+    }
+    let mut tlvs = &payload[addresses_len..];
+    while !tlvs.is_empty() {
+        if tlvs.len() < 3 {
+            return Err(invalid_data("truncated PROXY v2 TLV"));
+        }
+        let len = usize::from(u16::from_be_bytes([tlvs[1], tlvs[2]]));
+        if tlvs.len() < 3 + len {
+            return Err(invalid_data("truncated PROXY v2 TLV"));
+        }
+        header.tlvs.push(Tlv {
+            kind: tlvs[0],
+            value: tlvs[3..3 + len].to_vec(),
+        });
+        tlvs = &tlvs[3 + len..];
+    }
+    Ok(header)
+}
+
+pub(crate) async fn read_header(
+    stream: &mut TcpStream,
+    timeout: Duration,
+) -> io::Result<Option<Header>> {
+    net::with_timeout(timeout, read_until_decoded(stream)).await
+}
+
+async fn read_until_decoded(stream: &mut TcpStream) -> io::Result<Option<Header>> {
+    let mut buffer = vec![0; 232];
+    let mut peeked = 0;
+    loop {
+        let n = stream.peek(&mut buffer).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if !starts_like_header(&buffer[..n]) {
+            return Ok(None);
+        }
+        if let Some((header, len)) = decode(&buffer[..n])? {
+            stream.read_exact(&mut buffer[..len]).await?;
+            return Ok(Some(header));
+        }
+        if n == buffer.len() {
+            let len = if buffer.starts_with(&SIGNATURE) {
+                16 + usize::from(u16::from_be_bytes([buffer[14], buffer[15]]))
+            } else {
+                buffer.len() * 2
+            };
+            buffer.resize(len, 0);
+        } else if n == peeked {
+            net::wait_for_more(stream, peeked).await?;
+        }
+        peeked = n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn addrs() -> (SocketAddr, SocketAddr) {
+        (
+            "192.0.2.1:4000".parse().unwrap(),
+            "198.51.100.2:443".parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn oversized_tlvs_are_rejected() {
+        let fits = ProxyProtocol::v2().tlv(0x04, vec![0; MAX_TLVS_LEN - 3]);
+        assert!(fits.validate().is_ok());
+        let (source, destination) = addrs();
+        let header = fits.encode(Transport::Stream, source, destination);
+        assert_eq!(decode(&header).unwrap().unwrap().1, header.len());
+
+        let too_long = ProxyProtocol::v2().tlv(0x04, vec![0; u16::MAX as usize + 1]);
+        assert!(matches!(too_long.validate(), Err(Error::InvalidConfig(_))));
+        let too_many = ProxyProtocol::v2()
+            .tlv(0x04, vec![0; MAX_TLVS_LEN / 2])
+            .tlv(0x04, vec![0; MAX_TLVS_LEN / 2]);
+        assert!(matches!(too_many.validate(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn v1_round_trips() {
+        let (source, destination) = addrs();
+        let bytes = encode_v1(source, destination);
+        let (header, len) = decode(&bytes).unwrap().unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(header.version, Version::V1);
+        assert_eq!(header.source, Some(source));
+        assert_eq!(header.destination, Some(destination));
+    }
+
+    #[test]
+    fn v1_partial_and_overlong() {
+        let (source, destination) = addrs();
+        let bytes = encode_v1(source, destination);
+        assert_eq!(decode(&bytes[..bytes.len() - 1]).unwrap(), None);
+        let overlong = [b"PROXY ".as_slice(), &[b'x'; 120]].concat();
+        assert!(decode(&overlong).is_err());
+        assert!(decode(b"PROXY TCP4 nonsense\r\n").is_err());
+    }
+
+    #[test]
+    fn v2_round_trips_with_tlvs() {
+        let (source, destination) = addrs();
+        let tlvs = [Tlv {
+            kind: 0x04,
+            value: b"abc".to_vec(),
+        }];
+        let bytes = encode_v2(Transport::Stream, source, destination, &tlvs);
+        let (header, len) = decode(&bytes).unwrap().unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(header.version, Version::V2);
+        assert_eq!(header.transport, Some(Transport::Stream));
+        assert_eq!(header.source, Some(source));
+        assert_eq!(header.tlvs, tlvs);
+        assert_eq!(decode(&bytes[..bytes.len() - 1]).unwrap(), None);
+    }
+
+    #[test]
+    fn v2_tlv_bounds_are_checked() {
+        let (source, destination) = addrs();
+        let mut bytes = encode_v2(Transport::Stream, source, destination, &[]);
+        // A TLV that claims more bytes than the header carries.
+        bytes.extend_from_slice(&[0x04, 0x00, 0x10, b'x']);
+        let len = (bytes.len() - 16) as u16;
+        bytes[14..16].copy_from_slice(&len.to_be_bytes());
+        assert!(decode(&bytes).is_err());
+
+        // A TLV cut off inside its own type and length fields.
+        bytes.truncate(bytes.len() - 2);
+        let len = (bytes.len() - 16) as u16;
+        bytes[14..16].copy_from_slice(&len.to_be_bytes());
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn v2_truncated_addresses_are_rejected() {
+        let (source, destination) = addrs();
+        let mut bytes = encode_v2(Transport::Stream, source, destination, &[]);
+        bytes.truncate(20);
+        bytes[14..16].copy_from_slice(&4_u16.to_be_bytes());
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[tokio::test]
+    async fn half_closed_partial_header_fails_fast() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        client.write_all(b"PROXY TCP4 192.0.2.1").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let error = read_header(&mut server, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn stalled_header_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        client.write_all(b"PROXY TCP4 192.0.2.1").await.unwrap();
+
+        let error = read_header(&mut server, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::address::Address;
+use crate::writer::FileWriter;
+use crate::Direction;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const HEADER: &str = "proxy-rs-recording 1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            _ => Err(invalid_data("unknown protocol")),
+        }
+    }
+}
+
+fn direction_as_str(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Upload => "up",
+        Direction::Download => "down",
+    }
+}
+
+fn parse_direction(s: &str) -> io::Result<Direction> {
+    match s {
+        "up" => Ok(Direction::Upload),
+        "down" => Ok(Direction::Download),
+        _ => Err(invalid_data("unknown direction")),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn hex_digit(digit: u8) -> io::Result<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(invalid_data("bad hex")),
+    }
+}
+
+fn decode_hex(hex: &str) -> io::Result<Vec<u8>> {
+    let hex = hex.as_bytes();
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid_data("odd hex length"));
+    }
+    hex.chunks_exact(2)
+        .map(|pair| Ok(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+        .collect()
+}
+
+// Percent-escapes the bytes that would break the space separated line format.
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        if c == '%' || c.is_whitespace() || c.is_control() {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                let _ = write!(escaped, "%{byte:02x}");
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn unescape_field(field: &str) -> io::Result<String> {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let [high, low, tail @ ..] = tail else {
+                return Err(invalid_data("truncated escape"));
+            };
+            bytes.push(hex_digit(*high)? << 4 | hex_digit(*low)?);
+            rest = tail;
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_data("escaped field is not utf-8"))
+}
+
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    writer: FileWriter,
+    started_at: Instant,
+    next_connection: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{HEADER}")?;
+        Ok(Self {
+            writer: FileWriter::spawn(writer)?,
+            started_at: Instant::now(),
+            next_connection: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub(crate) fn open(&self, protocol: Protocol, client_addr: &Address) -> u64 {
+        let connection = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let micros = self.started_at.elapsed().as_micros();
+        self.write_line(format!(
+            "open {connection} {} {} {micros}",
+            protocol.as_str(),
+            escape_field(&client_addr.to_string())
+        ));
+        connection
+    }
+
+    pub(crate) fn record(&self, connection: u64, direction: Direction, bytes: &[u8]) {
+        let micros = self.started_at.elapsed().as_micros();
+        self.write_line(format!(
+            "data {connection} {} {micros} {}",
+            direction_as_str(direction),
+            encode_hex(bytes)
+        ));
+    }
+
+    pub(crate) fn close(&self, connection: u64) {
+        let micros = self.started_at.elapsed().as_micros();
+        self.write_line(format!("close {connection} {micros}"));
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_line(&self, mut line: String) {
+        line.push('\n');
+        self.writer.write(line.into_bytes());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub direction: Direction,
+    pub offset: Duration,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedConnection {
+    pub protocol: Protocol,
+    pub client_addr: Address,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Exchange {
+    pub(crate) request: Vec<u8>,
+    pub(crate) responses: Vec<Vec<u8>>,
+}
+
+impl RecordedConnection {
+    pub(crate) fn exchanges(&self) -> Vec<Exchange> {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+        for chunk in &self.chunks {
+            let starts_exchange = match chunk.direction {
+                Direction::Upload => exchanges.last().is_none_or(|e| !e.responses.is_empty()),
+                Direction::Download => exchanges.is_empty(),
+            };
+            if starts_exchange {
+                exchanges.push(Exchange::default());
+            }
+            let exchange = exchanges.last_mut().unwrap();
+            match chunk.direction {
+                Direction::Upload => exchange.request.extend_from_slice(&chunk.bytes),
+                Direction::Download => exchange.responses.push(chunk.bytes.clone()),
+            }
+        }
+        exchanges
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub connections: Vec<RecordedConnection>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid_data("missing recording header"));
+        }
+        let mut connections: Vec<(u64, RecordedConnection)> = Vec::new();
+        for line in lines {
+            let line = line?;
+            let fields: Vec<&str> = line.split(' ').collect();
+            let parse_id = |s: &str| s.parse::<u64>().map_err(|_| invalid_data("bad id"));
+            let parse_offset = |s: &str| {
+                s.parse::<u64>()
+                    .map(Duration::from_micros)
+                    .map_err(|_| invalid_data("bad offset"))
+            };
+            match fields.as_slice() {
+                ["open", id, protocol, client_addr, _] => {
+                    connections.push((
+                        parse_id(id)?,
+                        RecordedConnection {
+                            protocol: protocol.parse()?,
+                            client_addr: unescape_field(client_addr)?
+                                .parse()
+                                .map_err(|_| invalid_data("bad address"))?,
+                            chunks: Vec::new(),
+                        },
+                    ));
+                }
+                ["data", id, direction, offset, hex] => {
+                    let id = parse_id(id)?;
+                    let (_, connection) = connections
+                        .iter_mut()
+                        .find(|(i, _)| *i == id)
+                        .ok_or_else(|| invalid_data("data for unknown connection"))?;
+                    connection.chunks.push(Chunk {
+                        direction: parse_direction(direction)?,
+                        offset: parse_offset(offset)?,
+                        bytes: decode_hex(hex)?,
+                    });
+                }
+                ["close", _, _] | [""] => {}
+                _ => return Err(invalid_data("malformed recording line")),
+            }
+        }
+        Ok(Self {
+            connections: connections.into_iter().map(|(_, c)| c).collect(),
+        })
+    }
+
+    pub(crate) fn connections(&self, protocol: Protocol) -> Vec<RecordedConnection> {
+        self.connections
+            .iter()
+            .filter(|c| c.protocol == protocol)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayMode {
+    #[default]
+    ConnectionOrder,
+    MatchRequests,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::UnixAddress;
+
+    #[test]
+    fn recorder_round_trips_through_writer() {
+        let path = std::env::temp_dir().join(format!("proxy-rs-record-{}", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let client_addr = Address::Inet("127.0.0.1:4000".parse().unwrap());
+        let connection = recorder.open(Protocol::Tcp, &client_addr);
+        recorder.record(connection, Direction::Upload, b"ping");
+        recorder.record(connection, Direction::Download, b"pong");
+        recorder.close(connection);
+        recorder.flush().unwrap();
+
+        let recording = Recording::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(recording.connections.len(), 1);
+        let connection = &recording.connections[0];
+        assert_eq!(connection.client_addr, client_addr);
+        let chunks: Vec<_> = connection
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.direction, chunk.bytes.as_slice()))
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                (Direction::Upload, &b"ping"[..]),
+                (Direction::Download, &b"pong"[..])
+            ]
+        );
+    }
+
+    #[test]
+    fn unix_addresses_with_spaces_round_trip() {
+        let path = std::env::temp_dir().join(format!("proxy-rs-escape-{}", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let client_addr = Address::Unix(UnixAddress::pathname("/tmp/my socket%20\n.sock"));
+        let connection = recorder.open(Protocol::Udp, &client_addr);
+        recorder.record(connection, Direction::Upload, b"ping");
+        recorder.flush().unwrap();
+
+        let recording = Recording::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(recording.connections[0].client_addr, client_addr);
+    }
+
+    #[test]
+    fn corrupt_hex_is_an_error() {
+        for hex in ["0", "zz", "\u{e9}\u{e9}", "0\u{e9}"] {
+            assert_eq!(
+                decode_hex(hex).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{hex:?}"
+            );
+        }
+        assert_eq!(decode_hex("00fF").unwrap(), [0x00, 0xff]);
+
+        let recording = format!("{HEADER}\nopen 0 tcp 127.0.0.1:1 0\ndata 0 up 0 \u{e9}\u{e9}\n");
+        let error = Recording::from_reader(recording.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::acl::AccessList;
+use crate::address::Address;
+use crate::error::Error;
+use crate::event::{self, Event};
+use crate::fault::FaultInjector;
+use crate::record::Protocol;
+use crate::{tcp, udp};
+use std::io;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+#[derive(Debug)]
+pub(crate) enum RouteBuilder {
+    Tcp(Box<tcp::ProxyBuilder>),
+    Udp(Box<udp::ProxyBuilder>),
+}
+
+impl RouteBuilder {
+    pub(crate) fn access_list(self, access_list: AccessList) -> Self {
+        match self {
+            RouteBuilder::Tcp(builder) => {
+                RouteBuilder::Tcp(Box::new(builder.access_list(access_list)))
+            }
+            RouteBuilder::Udp(builder) => {
+                RouteBuilder::Udp(Box::new(builder.access_list(access_list)))
+            }
+        }
+    }
+
+    pub(crate) fn faults(self, faults: FaultInjector) -> Self {
+        match self {
+            RouteBuilder::Tcp(builder) => RouteBuilder::Tcp(Box::new(builder.faults(faults))),
+            RouteBuilder::Udp(builder) => RouteBuilder::Udp(Box::new(builder.faults(faults))),
+        }
+    }
+
+    pub(crate) async fn build(
+        self,
+        name: &str,
+        event_sender: Option<&mpsc::Sender<Event>>,
+    ) -> Result<(Route, Option<JoinHandle<()>>), Error> {
+        match (self, event_sender) {
+            (RouteBuilder::Tcp(builder), Some(event_sender)) => {
+                let (sender, receiver) = mpsc::channel(event_sender.max_capacity());
+                let route = Route::Tcp(builder.event_sender(sender).build().await?);
+                let forward = tokio::spawn(forward(
+                    name.to_owned(),
+                    receiver,
+                    event_sender.clone(),
+                    Event::Tcp,
+                ));
+                Ok((route, Some(forward)))
+            }
+            (RouteBuilder::Udp(builder), Some(event_sender)) => {
+                let (sender, receiver) = mpsc::channel(event_sender.max_capacity());
+                let route = Route::Udp(builder.event_sender(sender).build().await?);
+                let forward = tokio::spawn(forward(
+                    name.to_owned(),
+                    receiver,
+                    event_sender.clone(),
+                    Event::Udp,
+                ));
+                Ok((route, Some(forward)))
+            }
+            (RouteBuilder::Tcp(builder), None) => Ok((Route::Tcp(builder.build().await?), None)),
+            (RouteBuilder::Udp(builder), None) => Ok((Route::Udp(builder.build().await?), None)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Route {
+    Tcp(tcp::Proxy),
+    Udp(udp::Proxy),
+}
+
+impl Route {
+    pub(crate) fn protocol(&self) -> Protocol {
+        match self {
+            Route::Tcp(_) => Protocol::Tcp,
+            Route::Udp(_) => Protocol::Udp,
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> Address {
+        match self {
+            Route::Tcp(proxy) => proxy.local_addr(),
+            Route::Udp(proxy) => proxy.local_addr(),
+        }
+    }
+
+    pub(crate) async fn run(self) -> io::Result<()> {
+        match self {
+            Route::Tcp(proxy) => proxy.run().await,
+            Route::Udp(proxy) => proxy.run().await,
+        }
+    }
+}
+
+async fn forward<E>(
+    name: String,
+    mut receiver: mpsc::Receiver<E>,
+    event_sender: mpsc::Sender<Event>,
+    wrap: fn(E) -> Event,
+) {
+    while let Some(event) = receiver.recv().await {
+        let event = Event::from(event::RouteEvent {
+            route: name.clone(),
+            event: Box::new(wrap(event)),
+        });
+        if event_sender.send(event).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::address::Address;
+use crate::net;
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_ALPN: u16 = 0x0010;
+const MAX_CLIENT_HELLO: usize = 1 << 16;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ClientHello {
+    pub(crate) server_name: Option<String>,
+    pub(crate) alpn_protocols: Vec<Vec<u8>>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buffer.len() < len {
+            return Err(invalid_data("truncated ClientHello"));
+        }
+        let (head, tail) = self.buffer.split_at(len);
+        self.buffer = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn vec_u8(&mut self) -> io::Result<Reader<'a>> {
+        let len = usize::from(self.u8()?);
+        Ok(Reader {
+            buffer: self.take(len)?,
+        })
+    }
+
+    fn vec_u16(&mut self) -> io::Result<Reader<'a>> {
+        let len = usize::from(self.u16()?);
+        Ok(Reader {
+            buffer: self.take(len)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+fn handshake_bytes(buffer: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let mut handshake = Vec::new();
+    let mut records = buffer;
+    loop {
+        if records.len() < 5 {
+            return Ok(None);
+        }
+        if records[0] != CONTENT_TYPE_HANDSHAKE {
+            return Err(invalid_data("not a TLS handshake"));
+        }
+        let len = usize::from(u16::from_be_bytes([records[3], records[4]]));
+        if records.len() < 5 + len {
+            return Ok(None);
+        }
+        handshake.extend_from_slice(&records[5..5 + len]);
+        records = &records[5 + len..];
+        if handshake.len() >= 4 {
+            let body_len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+            if body_len as usize > MAX_CLIENT_HELLO {
+                return Err(invalid_data("ClientHello too large"));
+            }
+            if handshake.len() >= 4 + body_len as usize {
+                return Ok(Some(handshake));
+            }
+        }
+    }
+}
+
+pub(crate) fn parse(buffer: &[u8]) -> io::Result<Option<ClientHello>> {
+    let Some(handshake) = handshake_bytes(buffer)? else {
+        return Ok(None);
+    };
+    let mut reader = Reader { buffer: &handshake };
+    if reader.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return Err(invalid_data("not a ClientHello"));
+    }
+    reader.take(3)?;
+    reader.take(2 + 32)?;
+    reader.vec_u8()?;
+    reader.vec_u16()?;
+    reader.vec_u8()?;
+    let mut client_hello = ClientHello::default();
+    if reader.is_empty() {
+        return Ok(Some(client_hello));
+    }
+    let mut extensions = reader.vec_u16()?;
+    while !extensions.is_empty() {
+        let kind = extensions.u16()?;
+        let mut data = extensions.vec_u16()?;
+        match kind {
+            EXTENSION_SERVER_NAME => {
+                let mut names = data.vec_u16()?;
+                while !names.is_empty() {
+                    let name_type = names.u8()?;
+                    let name = names.vec_u16()?.buffer;
+                    if name_type == 0 {
+                        let name = std::str::from_utf8(name)
+                            .map_err(|_| invalid_data("invalid server name"))?;
+                        client_hello.server_name = Some(name.to_ascii_lowercase());
+                    }
+                }
+            }
+            EXTENSION_ALPN => {
+                let mut protocols = data.vec_u16()?;
+                while !protocols.is_empty() {
+                    client_hello
+                        .alpn_protocols
+                        .push(protocols.vec_u8()?.buffer.to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Some(client_hello))
+}
+
+pub(crate) async fn peek_client_hello(
+    stream: &TcpStream,
+    timeout: Duration,
+) -> io::Result<ClientHello> {
+    net::with_timeout(timeout, peek_until_parsed(stream)).await
+}
+
+async fn peek_until_parsed(stream: &TcpStream) -> io::Result<ClientHello> {
+    let mut buffer = vec![0; 1024];
+    let mut peeked = 0;
+    loop {
+        let n = stream.peek(&mut buffer).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(client_hello) = parse(&buffer[..n])? {
+            return Ok(client_hello);
+        }
+        if n == buffer.len() {
+            if buffer.len() >= MAX_CLIENT_HELLO {
+                return Err(invalid_data("ClientHello too large"));
+            }
+            buffer.resize(buffer.len() * 2, 0);
+        } else if n == peeked {
+            net::wait_for_more(stream, peeked).await?;
+        }
+        peeked = n;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SniRoutes {
+    routes: Vec<(String, Vec<Address>)>,
+}
+
+impl SniRoutes {
+    pub(crate) fn insert(&mut self, pattern: &str, remote_addrs: Vec<Address>) {
+        let pattern = pattern.to_ascii_lowercase();
+        match self.routes.iter_mut().find(|(p, _)| *p == pattern) {
+            Some((_, addrs)) => addrs.extend(remote_addrs),
+            None => self.routes.push((pattern, remote_addrs)),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub(crate) fn get(&self, server_name: &str) -> Option<&[Address]> {
+        let server_name = server_name.to_ascii_lowercase();
+        if let Some((_, addrs)) = self.routes.iter().find(|(p, _)| *p == server_name) {
+            return Some(addrs);
+        }
+        self.routes
+            .iter()
+            .filter_map(|(pattern, addrs)| {
+                let suffix = pattern.strip_prefix('*')?;
+                let matches = suffix.starts_with('.')
+                    && server_name.len() > suffix.len()
+                    && server_name.ends_with(suffix);
+                matches.then_some((suffix.len(), addrs))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, addrs)| addrs.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn with_u16_len(body: &[u8]) -> Vec<u8> {
+        let mut bytes = (body.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let mut name = vec![0];
+        name.extend_from_slice(&with_u16_len(server_name.as_bytes()));
+        let mut extension = EXTENSION_SERVER_NAME.to_be_bytes().to_vec();
+        extension.extend_from_slice(&with_u16_len(&with_u16_len(&name)));
+
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[0; 32]);
+        body.push(0);
+        body.extend_from_slice(&with_u16_len(&[0x13, 0x01]));
+        body.extend_from_slice(&[1, 0]);
+        body.extend_from_slice(&with_u16_len(&extension));
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&body);
+
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 3, 1];
+        record.extend_from_slice(&with_u16_len(&handshake));
+        record
+    }
+
+    #[test]
+    fn parses_server_name() {
+        let client_hello = parse(&client_hello("Example.COM")).unwrap().unwrap();
+        assert_eq!(client_hello.server_name.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn truncated_client_hello_needs_more_data() {
+        let bytes = client_hello("example.com");
+        for len in [0, 3, 5, bytes.len() - 1] {
+            assert_eq!(parse(&bytes[..len]).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn oversized_client_hello_is_rejected() {
+        let mut bytes = vec![CONTENT_TYPE_HANDSHAKE, 3, 1, 0, 4];
+        bytes.extend_from_slice(&[HANDSHAKE_CLIENT_HELLO, 0xff, 0xff, 0xff]);
+        let error = parse(&bytes).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn non_handshake_is_rejected() {
+        let error = parse(b"GET / HTTP/1.1\r\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn half_closed_partial_client_hello_fails_fast() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client
+            .write_all(&client_hello("example.com")[..10])
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let error = peek_client_hello(&server, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn stalled_client_hello_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client
+            .write_all(&client_hello("example.com")[..10])
+            .await
+            .unwrap();
+
+        let error = peek_client_hello(&server, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::address::Address;
+use crate::net;
+use crate::transport::Connector;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub(crate) const VERSION: u8 = 0x05;
+pub(crate) const AUTH_VERSION: u8 = 0x01;
+
+pub(crate) const METHOD_NO_AUTH: u8 = 0x00;
+pub(crate) const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+pub(crate) const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+
+pub(crate) const COMMAND_CONNECT: u8 = 0x01;
+pub(crate) const COMMAND_UDP_ASSOCIATE: u8 = 0x03;
+
+pub(crate) const REPLY_SUCCEEDED: u8 = 0x00;
+pub(crate) const REPLY_GENERAL_FAILURE: u8 = 0x01;
+pub(crate) const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+pub(crate) const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+pub(crate) const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub(crate) const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Socks5Config {
+    credentials: Vec<(String, String)>,
+}
+
+impl Socks5Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials.push((username.into(), password.into()));
+        self
+    }
+
+    pub(crate) fn method(&self) -> u8 {
+        if self.credentials.is_empty() {
+            METHOD_NO_AUTH
+        } else {
+            METHOD_USERNAME_PASSWORD
+        }
+    }
+
+    pub(crate) fn authenticate(&self, username: &[u8], password: &[u8]) -> bool {
+        self.credentials
+            .iter()
+            .any(|(u, p)| u.as_bytes() == username && p.as_bytes() == password)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Target {
+    Socket(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Target {
+    pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Self> {
+        let atyp = reader.read_u8().await?;
+        let ip = match atyp {
+            ATYP_IPV4 => {
+                let mut octets = [0; 4];
+                reader.read_exact(&mut octets).await?;
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            ATYP_IPV6 => {
+                let mut octets = [0; 16];
+                reader.read_exact(&mut octets).await?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            ATYP_DOMAIN => {
+                let len = reader.read_u8().await?;
+                let mut domain = vec![0; usize::from(len)];
+                reader.read_exact(&mut domain).await?;
+                let domain =
+                    String::from_utf8(domain).map_err(|_| invalid_data("invalid domain name"))?;
+                let port = reader.read_u16().await?;
+                return Ok(Target::Domain(domain, port));
+            }
+            _ => return Err(invalid_data("unsupported address type")),
+        };
+        let port = reader.read_u16().await?;
+        Ok(Target::Socket(SocketAddr::new(ip, port)))
+    }
+
+    pub(crate) fn decode(buffer: &[u8]) -> io::Result<(Self, usize)> {
+        let truncated = || invalid_data("truncated address");
+        let (&atyp, rest) = buffer.split_first().ok_or_else(truncated)?;
+        let (address, len) = match atyp {
+            ATYP_IPV4 => {
+                let octets = <[u8; 4]>::try_from(rest.get(..4).ok_or_else(truncated)?).unwrap();
+                (Err(IpAddr::V4(octets.into())), 4)
+            }
+            ATYP_IPV6 => {
+                let octets = <[u8; 16]>::try_from(rest.get(..16).ok_or_else(truncated)?).unwrap();
+                (Err(IpAddr::V6(octets.into())), 16)
+            }
+            ATYP_DOMAIN => {
+                let len = usize::from(*rest.first().ok_or_else(truncated)?);
+                let domain = rest.get(1..1 + len).ok_or_else(truncated)?;
+                let domain = String::from_utf8(domain.to_vec())
+                    .map_err(|_| invalid_data("invalid domain name"))?;
+                (Ok(domain), 1 + len)
+            }
+            _ => return Err(invalid_data("unsupported address type")),
+        };
+        let port = rest.get(len..len + 2).ok_or_else(truncated)?;
+        let port = u16::from_be_bytes([port[0], port[1]]);
+        let address = match address {
+            Ok(domain) => Target::Domain(domain, port),
+            Err(ip) => Target::Socket(SocketAddr::new(ip, port)),
+        };
+        Ok((address, 1 + len + 2))
+    }
+
+    pub(crate) fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Target::Socket(addr) => {
+                match addr.ip() {
+                    IpAddr::V4(ip) => {
+                        buffer.push(ATYP_IPV4);
+                        buffer.extend_from_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        buffer.push(ATYP_IPV6);
+                        buffer.extend_from_slice(&ip.octets());
+                    }
+                }
+                buffer.extend_from_slice(&addr.port().to_be_bytes());
+            }
+            Target::Domain(domain, port) => {
+                buffer.push(ATYP_DOMAIN);
+                buffer.push(domain.len() as u8);
+                buffer.extend_from_slice(domain.as_bytes());
+                buffer.extend_from_slice(&port.to_be_bytes());
+            }
+        }
+    }
+
+    pub(crate) async fn resolve(
+        &self,
+        connector: Option<&dyn Connector>,
+    ) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            Target::Socket(addr) => Ok(vec![*addr]),
+            Target::Domain(domain, port) => net::resolve(connector, domain, *port).await,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Socket(addr) => write!(f, "{addr}"),
+            Target::Domain(domain, port) => write!(f, "{domain}:{port}"),
+        }
+    }
+}
+
+pub(crate) fn reply(code: u8, bound: &Address) -> Vec<u8> {
+    let bound = bound.inet().unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+    let mut reply = vec![VERSION, code, 0x00];
+    Target::Socket(bound).encode(&mut reply);
+    reply
+}
+
+pub(crate) fn udp_header(address: &Target) -> Vec<u8> {
+    let mut header = vec![0x00, 0x00, 0x00];
+    address.encode(&mut header);
+    header
+}
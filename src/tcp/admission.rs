@@ -0,0 +1,35 @@
+use crate::address::Address;
+use crate::tls::ClientIdentity;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Admission {
+    pub client_addr: Address,
+    pub local_addr: Address,
+    pub identity: Option<ClientIdentity>,
+}
+
+type Hook = dyn Fn(&Admission) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct AdmissionHook(Arc<Hook>);
+
+impl AdmissionHook {
+    pub(crate) fn new<F>(hook: F) -> Self
+    where
+        F: Fn(&Admission) -> bool + Send + Sync + 'static,
+    {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn admit(&self, admission: &Admission) -> bool {
+        (self.0)(admission)
+    }
+}
+
+impl fmt::Debug for AdmissionHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AdmissionHook")
+    }
+}
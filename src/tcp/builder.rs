@@ -1,16 +1,62 @@
+use super::admission::{Admission, AdmissionHook};
+use super::framer::{Framer, Raw};
 use super::{Event, Proxy};
+use crate::acl::{AccessList, Cidr};
+use crate::address::{Address, UnixAddress};
+use crate::capture::Capture;
+use crate::chain::UpstreamProxy;
+use crate::error::Error;
+use crate::fault::FaultInjector;
+use crate::http::{ConnectConfig, HttpConfig};
+use crate::limit::{Buckets, KeyedBuckets, Limits, RateLimit};
+use crate::net::Listener;
+use crate::proxy_protocol::{ProxyProtocol, ProxyProtocolMode};
+use crate::record::Recorder;
+use crate::sni::SniRoutes;
+use crate::socks::Socks5Config;
+use crate::tls::{TlsClientConfig, TlsServerConfig};
+use crate::transport::{self, Connector};
+use crate::Direction;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io;
-use tokio::net::{lookup_host, TcpListener, ToSocketAddrs};
+use tokio::net::{lookup_host, ToSocketAddrs};
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
 
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 pub struct ProxyBuilder {
     local_addrs_handles: JoinSet<io::Result<Vec<SocketAddr>>>,
     remote_addrs_handles: JoinSet<io::Result<Vec<SocketAddr>>>,
+    sni_routes_handles: JoinSet<io::Result<(String, Vec<SocketAddr>)>>,
+    local_unix_addr: Option<UnixAddress>,
+    remote_addresses: Vec<Address>,
     event_sender: Option<mpsc::Sender<Event>>,
     buffer_size: usize,
+    access_list: AccessList,
+    connection_limits: Limits,
+    client_limits: Limits,
+    global_limits: Limits,
+    faults: FaultInjector,
+    recorder: Option<Recorder>,
+    capture: Option<Capture>,
+    proxy_protocol: Option<ProxyProtocol>,
+    accept_proxy_protocol: Option<ProxyProtocolMode>,
+    trusted_proxies: Vec<Cidr>,
+    handshake_timeout: Duration,
+    tls: Option<TlsServerConfig>,
+    upstream_tls: Option<TlsClientConfig>,
+    admission: Option<AdmissionHook>,
+    http: Option<HttpConfig>,
+    connect: Option<ConnectConfig>,
+    socks5: Option<Socks5Config>,
+    upstream_proxies: Vec<UpstreamProxy>,
+    framer: Arc<dyn Framer>,
+    listener: Option<Box<dyn transport::Listener>>,
+    connector: Option<Box<dyn Connector>>,
 }
 
 impl ProxyBuilder {
@@ -18,8 +64,32 @@ impl ProxyBuilder {
         Self {
             local_addrs_handles: JoinSet::new(),
             remote_addrs_handles: JoinSet::new(),
+            sni_routes_handles: JoinSet::new(),
+            local_unix_addr: None,
+            remote_addresses: Vec::new(),
             event_sender: None,
             buffer_size: 1024,
+            access_list: AccessList::new(),
+            connection_limits: Limits::default(),
+            client_limits: Limits::default(),
+            global_limits: Limits::default(),
+            faults: FaultInjector::default(),
+            recorder: None,
+            capture: None,
+            proxy_protocol: None,
+            accept_proxy_protocol: None,
+            trusted_proxies: Vec::new(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            tls: None,
+            upstream_tls: None,
+            admission: None,
+            http: None,
+            connect: None,
+            socks5: None,
+            upstream_proxies: Vec::new(),
+            framer: Arc::new(Raw),
+            listener: None,
+            connector: None,
         }
     }
     pub fn local_addrs<A: ToSocketAddrs + Send + 'static>(mut self, local_addrs: A) -> Self {
@@ -40,6 +110,37 @@ impl ProxyBuilder {
         self
     }
 
+    #[cfg(unix)]
+    pub fn local_unix_addr(mut self, local_addr: UnixAddress) -> Self {
+        self.local_unix_addr = Some(local_addr);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn remote_unix_addr(mut self, remote_addr: UnixAddress) -> Self {
+        self.remote_addresses.push(Address::Unix(remote_addr));
+        self
+    }
+
+    pub fn remote_address(mut self, remote_addr: impl Into<Address>) -> Self {
+        self.remote_addresses.push(remote_addr.into());
+        self
+    }
+
+    pub fn sni_route<A: ToSocketAddrs + Send + 'static>(
+        mut self,
+        server_name: impl Into<String>,
+        remote_addrs: A,
+    ) -> Self {
+        let server_name = server_name.into();
+        self.sni_routes_handles.spawn(async move {
+            lookup_host(remote_addrs)
+                .await
+                .map(|lookup_host| (server_name, lookup_host.collect()))
+        });
+        self
+    }
+
     pub fn event_sender(mut self, event_sender: mpsc::Sender<Event>) -> Self {
         self.event_sender = Some(event_sender);
         self
@@ -50,25 +151,271 @@ impl ProxyBuilder {
         self
     }
 
-    pub async fn build(mut self) -> io::Result<Proxy> {
-        let mut local_addrs = Vec::new();
-        while let Some(l) = self.local_addrs_handles.join_next().await {
-            local_addrs.extend(l.unwrap()?);
+    pub fn framer<F: Framer + 'static>(mut self, framer: F) -> Self {
+        self.framer = Arc::new(framer);
+        self
+    }
+
+    pub fn allow(self, cidr: Cidr) -> Self {
+        self.access_list.allow(cidr);
+        self
+    }
+
+    pub fn deny(self, cidr: Cidr) -> Self {
+        self.access_list.deny(cidr);
+        self
+    }
+
+    pub fn access_list(mut self, access_list: AccessList) -> Self {
+        self.access_list = access_list;
+        self
+    }
+
+    pub fn connection_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.connection_limits.set(direction, limit);
+        self
+    }
+
+    pub fn client_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.client_limits.set(direction, limit);
+        self
+    }
+
+    pub fn global_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.global_limits.set(direction, limit);
+        self
+    }
+
+    pub fn faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub fn recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn send_proxy_protocol(mut self, proxy_protocol: ProxyProtocol) -> Self {
+        self.proxy_protocol = Some(proxy_protocol);
+        self
+    }
+
+    pub fn accept_proxy_protocol(mut self, mode: ProxyProtocolMode) -> Self {
+        self.accept_proxy_protocol = Some(mode);
+        self
+    }
+
+    pub fn trusted_proxy(mut self, cidr: Cidr) -> Self {
+        self.trusted_proxies.push(cidr);
+        self
+    }
+
+    pub fn handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsServerConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn upstream_tls(mut self, upstream_tls: TlsClientConfig) -> Self {
+        self.upstream_tls = Some(upstream_tls);
+        self
+    }
+
+    pub fn admission<F>(mut self, admission: F) -> Self
+    where
+        F: Fn(&Admission) -> bool + Send + Sync + 'static,
+    {
+        self.admission = Some(AdmissionHook::new(admission));
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    pub fn connect(mut self, connect: ConnectConfig) -> Self {
+        self.connect = Some(connect);
+        self
+    }
+
+    pub fn socks5(mut self, socks5: Socks5Config) -> Self {
+        self.socks5 = Some(socks5);
+        self
+    }
+
+    pub fn upstream_proxy(mut self, upstream_proxy: UpstreamProxy) -> Self {
+        self.upstream_proxies.push(upstream_proxy);
+        self
+    }
+
+    pub fn listener(mut self, listener: impl transport::Listener + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.connector = Some(Box::new(connector));
+        self
+    }
+
+    pub async fn build(mut self) -> Result<Proxy, Error> {
+        let local_addrs: Vec<SocketAddr> = Error::resolve_all(&mut self.local_addrs_handles)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        let remote_addrs: Vec<SocketAddr> = Error::resolve_all(&mut self.remote_addrs_handles)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut sni_routes = SniRoutes::default();
+        for (server_name, remote_addrs) in Error::resolve_all(&mut self.sni_routes_handles).await? {
+            sni_routes.insert(
+                &server_name,
+                remote_addrs.into_iter().map(Address::Inet).collect(),
+            );
+        }
+        let is_tcp_listener = self.listener.is_none() && self.local_unix_addr.is_none();
+        if !is_tcp_listener && (self.accept_proxy_protocol.is_some() || !sni_routes.is_empty()) {
+            return Err(Error::InvalidConfig(
+                "PROXY protocol and SNI routing require a tcp listener",
+            ));
+        }
+        if let Some(proxy_protocol) = &self.proxy_protocol {
+            proxy_protocol.validate()?;
         }
-        let mut remote_addrs = Vec::new();
-        while let Some(r) = self.remote_addrs_handles.join_next().await {
-            remote_addrs.extend(r.unwrap()?);
+        for upstream_proxy in &self.upstream_proxies {
+            upstream_proxy.validate()?;
         }
-        let listener = TcpListener::bind(&*local_addrs).await?;
-        let remote_addrs = remote_addrs;
+        for limits in [
+            &self.connection_limits,
+            &self.client_limits,
+            &self.global_limits,
+        ] {
+            limits.validate()?;
+        }
+        let has_routes = self.http.is_some()
+            || self.connect.is_some()
+            || self.socks5.is_some()
+            || !sni_routes.is_empty();
+        if remote_addrs.is_empty() && self.remote_addresses.is_empty() && !has_routes {
+            return Err(Error::NoRemoteAddresses);
+        }
+        let listener =
+            match (self.listener, self.local_unix_addr) {
+                (None, None) if local_addrs.is_empty() => return Err(Error::NoLocalAddresses),
+                (None, None) => Listener::bind_tcp(&local_addrs)
+                    .await
+                    .map_err(Error::Bind)?,
+                #[cfg(unix)]
+                (None, Some(local_unix_addr)) if local_addrs.is_empty() => {
+                    Listener::bind_unix(&local_unix_addr).map_err(Error::Bind)?
+                }
+                (Some(listener), None) if local_addrs.is_empty() => Listener::Custom(listener),
+                _ => return Err(Error::InvalidConfig(
+                    "tcp proxy listens on one of local_addrs, a unix socket or a custom listener",
+                )),
+            };
+        let local_addr = listener.local_addr().map_err(Error::Bind)?;
+        let remote_addrs: Vec<Address> = remote_addrs
+            .into_iter()
+            .map(Address::Inet)
+            .chain(self.remote_addresses)
+            .collect();
         let event_sender = self.event_sender;
         let buffer_size = self.buffer_size;
+        let access_list = self.access_list;
+        let connection_limits = self.connection_limits;
+        let faults = self.faults;
+        let recorder = self.recorder;
+        let capture = self.capture;
+        let proxy_protocol = self.proxy_protocol;
+        let accept_proxy_protocol = self.accept_proxy_protocol;
+        let trusted_proxies = self.trusted_proxies;
+        let handshake_timeout = self.handshake_timeout;
+        let tls_config = match self.tls {
+            Some(tls) => Some(Arc::new(tls.build().map_err(Error::Tls)?)),
+            None => None,
+        };
+        let tls_connector = self
+            .upstream_tls
+            .map(TlsClientConfig::build)
+            .transpose()
+            .map_err(Error::Tls)?;
+        let admission = self.admission;
+        let http = match self.http {
+            Some(http) => Some(http.build().await?),
+            None => None,
+        };
+        let connect = self.connect;
+        let socks5 = self.socks5;
+        let upstream_proxies = self.upstream_proxies;
+        let client_buckets = KeyedBuckets::new(self.client_limits);
+        let global_buckets = Arc::new(Buckets::new(self.global_limits));
+        let framer = self.framer;
+        let connector = self.connector;
 
         Ok(Proxy {
             listener,
+            local_addr,
             remote_addrs,
+            sni_routes,
             event_sender,
             buffer_size,
+            access_list,
+            connection_limits,
+            client_buckets,
+            global_buckets,
+            faults,
+            recorder,
+            capture,
+            proxy_protocol,
+            accept_proxy_protocol,
+            trusted_proxies,
+            handshake_timeout,
+            tls_config,
+            tls_connector,
+            admission,
+            http,
+            connect,
+            socks5,
+            upstream_proxies,
+            connector,
+            framer,
         })
     }
 }
+
+impl Default for ProxyBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn v1_with_tlvs_is_rejected() {
+        let result = ProxyBuilder::new()
+            .local_addrs("127.0.0.1:0")
+            .remote_addrs("127.0.0.1:9")
+            .send_proxy_protocol(ProxyProtocol::v1().tlv(0x04, "noop"))
+            .build()
+            .await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+}
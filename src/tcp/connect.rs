@@ -0,0 +1,130 @@
+use super::framer::FrameBuffer;
+use super::http::Connection;
+use super::stream::Stream;
+use super::{event, Client, Event, Proxy};
+use crate::address::Address;
+use crate::http::{self, ConnectConfig, RequestHead};
+use crate::net;
+use crate::Direction;
+use std::sync::Arc;
+use tokio::io::{self, AsyncWriteExt};
+
+const ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+
+impl Proxy {
+    pub(super) async fn serve_connect(
+        self: &Arc<Self>,
+        connect: &ConnectConfig,
+        client_stream: Stream,
+        mut client: Client,
+    ) {
+        let mut downstream = Connection::new(client_stream);
+        let head = net::with_timeout(
+            self.handshake_timeout,
+            downstream.read_head(self.buffer_size, RequestHead::parse),
+        )
+        .await;
+        let request = match head {
+            Ok(Some((request, _))) => request,
+            Ok(None) => return,
+            Err(error) => {
+                let response = match error.kind() {
+                    io::ErrorKind::InvalidData => Some(http::error_response(400, "Bad Request")),
+                    io::ErrorKind::TimedOut => Some(http::error_response(408, "Request Timeout")),
+                    _ => None,
+                };
+                if let Some(response) = response {
+                    let _ = downstream.stream.write_all(&response).await;
+                }
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error,
+                }))
+                .await;
+                return;
+            }
+        };
+        if !request.method.eq_ignore_ascii_case("CONNECT") {
+            let response = http::error_response(405, "Method Not Allowed");
+            let _ = downstream.stream.write_all(&response).await;
+            return;
+        }
+        let Some((host, port)) = http::parse_authority(&request.target) else {
+            let response = http::error_response(400, "Bad Request");
+            let _ = downstream.stream.write_all(&response).await;
+            return;
+        };
+        if !connect.allows_target(&host, port) {
+            self.reject_connect(&mut downstream, &client).await;
+            return;
+        }
+        let remote_addrs: Vec<Address> =
+            match net::resolve(self.connector.as_deref(), &host, port).await {
+                Ok(addrs) => addrs
+                    .into_iter()
+                    .filter(|addr| connect.allows_addr(*addr))
+                    .map(Address::Inet)
+                    .collect(),
+                Err(error) => {
+                    self.send_event(Event::from(event::ConnectionError {
+                        local_addr: self.local_addr(),
+                        error,
+                    }))
+                    .await;
+                    let response = http::error_response(502, "Bad Gateway");
+                    let _ = downstream.stream.write_all(&response).await;
+                    return;
+                }
+            };
+        if remote_addrs.is_empty() {
+            self.reject_connect(&mut downstream, &client).await;
+            return;
+        }
+
+        client.target = Some(request.target.clone());
+        let Some((remote_stream, remote_addr)) = self.open_remote(&remote_addrs, &client).await
+        else {
+            let response = http::error_response(502, "Bad Gateway");
+            let _ = downstream.stream.write_all(&response).await;
+            return;
+        };
+        if let Err(error) = downstream.stream.write_all(ESTABLISHED).await {
+            self.send_event(Event::from(event::ConnectionError {
+                local_addr: self.local_addr(),
+                error,
+            }))
+            .await;
+            return;
+        }
+        let session = self
+            .open_session(&client, &downstream.stream, &remote_stream, remote_addr)
+            .await;
+        let mut remote_stream = remote_stream;
+        if !downstream.buffer.is_empty() {
+            let mut pending = FrameBuffer::new();
+            let _ = self
+                .transfer(
+                    &mut remote_stream,
+                    &downstream.buffer,
+                    Direction::Upload,
+                    &session,
+                    &mut pending,
+                )
+                .await;
+            self.flush_frames(Direction::Upload, &session, pending)
+                .await;
+        }
+        self.forward(downstream.stream, remote_stream, session)
+            .await;
+    }
+
+    async fn reject_connect(&self, downstream: &mut Connection, client: &Client) {
+        let response = http::error_response(403, "Forbidden");
+        let _ = downstream.stream.write_all(&response).await;
+        self.send_event(Event::from(event::Rejected {
+            client_addr: client.addr.clone(),
+            local_addr: self.local_addr(),
+        }))
+        .await;
+    }
+}
@@ -1,20 +1,41 @@
+use crate::address::Address;
+use crate::tls::ClientIdentity;
 use std::io;
-use std::net::SocketAddr;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Event {
+    Listening(Listening),
     Connection(Connection),
     ConnectionError(ConnectionError),
     Disconnection(Disconnection),
     Message(Message),
     MessageError(MessageError),
+    Rejected(Rejected),
+    RateLimited(RateLimited),
+    TlsError(TlsError),
+    HttpExchange(Box<HttpExchange>),
+}
+
+#[derive(Debug)]
+pub struct Listening {
+    pub local_addr: Address,
+}
+impl From<Listening> for Event {
+    fn from(value: Listening) -> Self {
+        Event::Listening(value)
+    }
 }
 
 #[derive(Debug)]
 pub struct Connection {
-    pub client_addr: SocketAddr,
-    pub local_addr: SocketAddr,
-    pub remote_addr: SocketAddr,
+    pub client_addr: Address,
+    pub local_addr: Address,
+    pub remote_addr: Address,
+    pub identity: Option<ClientIdentity>,
+    pub server_name: Option<String>,
+    pub alpn_protocols: Vec<Vec<u8>>,
+    pub target: Option<String>,
 }
 impl From<Connection> for Event {
     fn from(value: Connection) -> Self {
@@ -24,7 +45,7 @@ impl From<Connection> for Event {
 
 #[derive(Debug)]
 pub struct ConnectionError {
-    pub local_addr: SocketAddr,
+    pub local_addr: Address,
     pub error: io::Error,
 }
 impl From<ConnectionError> for Event {
@@ -35,9 +56,10 @@ impl From<ConnectionError> for Event {
 
 #[derive(Debug)]
 pub struct Disconnection {
-    pub client_addr: SocketAddr,
-    pub local_addr: SocketAddr,
-    pub remote_addr: SocketAddr,
+    pub client_addr: Address,
+    pub local_addr: Address,
+    pub remote_addr: Address,
+    pub identity: Option<ClientIdentity>,
 }
 impl From<Disconnection> for Event {
     fn from(value: Disconnection) -> Self {
@@ -47,10 +69,12 @@ impl From<Disconnection> for Event {
 
 #[derive(Debug)]
 pub struct Message {
-    pub from_addr: SocketAddr,
-    pub local_addr: SocketAddr,
-    pub to_addr: SocketAddr,
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
     pub message: String,
+    pub truncated: bool,
+    pub identity: Option<ClientIdentity>,
 }
 impl From<Message> for Event {
     fn from(value: Message) -> Self {
@@ -60,9 +84,9 @@ impl From<Message> for Event {
 
 #[derive(Debug)]
 pub struct MessageError {
-    pub from_addr: SocketAddr,
-    pub local_addr: SocketAddr,
-    pub to_addr: SocketAddr,
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
     pub error: io::Error,
 }
 impl From<MessageError> for Event {
@@ -70,3 +94,62 @@ impl From<MessageError> for Event {
         Event::MessageError(value)
     }
 }
+
+#[derive(Debug)]
+pub struct Rejected {
+    pub client_addr: Address,
+    pub local_addr: Address,
+}
+impl From<Rejected> for Event {
+    fn from(value: Rejected) -> Self {
+        Event::Rejected(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimited {
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
+}
+impl From<RateLimited> for Event {
+    fn from(value: RateLimited) -> Self {
+        Event::RateLimited(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct TlsError {
+    pub client_addr: Address,
+    pub local_addr: Address,
+    pub error: io::Error,
+}
+impl From<TlsError> for Event {
+    fn from(value: TlsError) -> Self {
+        Event::TlsError(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpExchange {
+    pub client_addr: Address,
+    pub local_addr: Address,
+    pub remote_addr: Address,
+    pub identity: Option<ClientIdentity>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub request_headers: Vec<(String, String)>,
+    pub response_headers: Vec<(String, String)>,
+    pub request_body_size: u64,
+    pub response_body_size: u64,
+    pub request_body: Option<Vec<u8>>,
+    pub response_body: Option<Vec<u8>>,
+    pub time_to_first_byte: Duration,
+    pub duration: Duration,
+}
+impl From<HttpExchange> for Event {
+    fn from(value: HttpExchange) -> Self {
+        Event::HttpExchange(Box::new(value))
+    }
+}
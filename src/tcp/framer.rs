@@ -0,0 +1,355 @@
+use std::fmt::Debug;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+pub trait Framer: Debug + Send + Sync {
+    fn next_frame(&self, buffer: &mut FrameBuffer) -> Option<Frame>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameBuffer {
+    pub bytes: Vec<u8>,
+    pub truncating: bool,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn split_off_chunk(&mut self, start: usize, len: usize) -> Frame {
+        self.truncating = true;
+        Frame::truncated(self.bytes.drain(start..start + len).collect())
+    }
+
+    pub fn finish(&mut self, bytes: Vec<u8>) -> Frame {
+        Frame {
+            bytes,
+            truncated: std::mem::take(&mut self.truncating),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub bytes: Vec<u8>,
+    pub truncated: bool,
+}
+
+impl Frame {
+    pub fn complete(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            truncated: false,
+        }
+    }
+
+    pub fn truncated(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            truncated: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Raw;
+
+impl Framer for Raw {
+    fn next_frame(&self, buffer: &mut FrameBuffer) -> Option<Frame> {
+        if buffer.is_empty() {
+            None
+        } else {
+            Some(Frame::complete(std::mem::take(&mut buffer.bytes)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lines {
+    max_frame_size: usize,
+}
+
+impl Lines {
+    pub fn new() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size.max(1);
+        self
+    }
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framer for Lines {
+    fn next_frame(&self, buffer: &mut FrameBuffer) -> Option<Frame> {
+        let line_len = buffer
+            .bytes
+            .iter()
+            .position(|&b| b == b'\n')
+            .unwrap_or(buffer.bytes.len());
+        if line_len > self.max_frame_size {
+            return Some(buffer.split_off_chunk(0, self.max_frame_size));
+        }
+        if line_len == buffer.bytes.len() {
+            return None;
+        }
+        let mut frame: Vec<u8> = buffer.bytes.drain(..=line_len).collect();
+        frame.pop();
+        if frame.last() == Some(&b'\r') {
+            frame.pop();
+        }
+        Some(buffer.finish(frame))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthWidth {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl LengthWidth {
+    fn len(self) -> usize {
+        match self {
+            LengthWidth::U8 => 1,
+            LengthWidth::U16 => 2,
+            LengthWidth::U32 => 4,
+            LengthWidth::U64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LengthPrefixed {
+    width: LengthWidth,
+    endianness: Endianness,
+    max_frame_size: usize,
+}
+
+impl LengthPrefixed {
+    pub fn new(width: LengthWidth, endianness: Endianness) -> Self {
+        Self {
+            width,
+            endianness,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size.max(1);
+        self
+    }
+
+    fn decode_len(&self, header: &[u8]) -> u64 {
+        let header_len = header.len();
+        let mut bytes = [0; 8];
+        match self.endianness {
+            Endianness::Big => {
+                bytes[8 - header_len..].copy_from_slice(header);
+                u64::from_be_bytes(bytes)
+            }
+            Endianness::Little => {
+                bytes[..header_len].copy_from_slice(header);
+                u64::from_le_bytes(bytes)
+            }
+        }
+    }
+
+    fn encode_len(&self, len: u64, header: &mut [u8]) {
+        let header_len = header.len();
+        match self.endianness {
+            Endianness::Big => header.copy_from_slice(&len.to_be_bytes()[8 - header_len..]),
+            Endianness::Little => header.copy_from_slice(&len.to_le_bytes()[..header_len]),
+        }
+    }
+}
+
+impl Framer for LengthPrefixed {
+    fn next_frame(&self, buffer: &mut FrameBuffer) -> Option<Frame> {
+        let header_len = self.width.len();
+        if buffer.bytes.len() < header_len {
+            return None;
+        }
+        let len = self.decode_len(&buffer.bytes[..header_len]);
+        if len > self.max_frame_size as u64 {
+            // Emit the oversized frame in max_frame_size chunks, rewriting the
+            // header with the remaining length so the stream stays in sync.
+            if buffer.bytes.len() - header_len < self.max_frame_size {
+                return None;
+            }
+            let chunk_len = self.max_frame_size;
+            let frame = buffer.split_off_chunk(header_len, chunk_len);
+            self.encode_len(len - chunk_len as u64, &mut buffer.bytes[..header_len]);
+            return Some(frame);
+        }
+        let frame_len = header_len + len as usize;
+        if buffer.bytes.len() < frame_len {
+            return None;
+        }
+        let frame = buffer.bytes[header_len..frame_len].to_vec();
+        buffer.bytes.drain(..frame_len);
+        Some(buffer.finish(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(framer: &dyn Framer, buffer: &mut FrameBuffer, bytes: &[u8]) -> Vec<Frame> {
+        buffer.extend_from_slice(bytes);
+        std::iter::from_fn(|| framer.next_frame(buffer)).collect()
+    }
+
+    #[test]
+    fn raw_emits_everything_buffered() {
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            frames(&Raw, &mut buffer, b"abc"),
+            [Frame::complete(b"abc".to_vec())]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn lines_split_across_reads() {
+        let framer = Lines::new();
+        let mut buffer = FrameBuffer::new();
+        assert!(frames(&framer, &mut buffer, b"hel").is_empty());
+        assert_eq!(
+            frames(&framer, &mut buffer, b"lo\r\nworld\nrest"),
+            [
+                Frame::complete(b"hello".to_vec()),
+                Frame::complete(b"world".to_vec())
+            ]
+        );
+        assert_eq!(buffer.bytes, b"rest");
+    }
+
+    #[test]
+    fn lines_oversized_without_newline_is_flushed_in_chunks() {
+        let framer = Lines::new().max_frame_size(4);
+        let mut buffer = FrameBuffer::new();
+        assert!(frames(&framer, &mut buffer, b"abc").is_empty());
+        assert_eq!(
+            frames(&framer, &mut buffer, b"defg"),
+            [Frame::truncated(b"abcd".to_vec())]
+        );
+        assert_eq!(buffer.bytes, b"efg");
+        assert_eq!(
+            frames(&framer, &mut buffer, b"\nok\n"),
+            [
+                Frame::truncated(b"efg".to_vec()),
+                Frame::complete(b"ok".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_oversized_before_newline_is_truncated() {
+        let framer = Lines::new().max_frame_size(4);
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            frames(&framer, &mut buffer, b"abcdef\nok\n"),
+            [
+                Frame::truncated(b"abcd".to_vec()),
+                Frame::truncated(b"ef".to_vec()),
+                Frame::complete(b"ok".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_at_max_frame_size_are_complete() {
+        let framer = Lines::new().max_frame_size(4);
+        let mut buffer = FrameBuffer::new();
+        assert!(frames(&framer, &mut buffer, b"abcd").is_empty());
+        assert_eq!(
+            frames(&framer, &mut buffer, b"\n"),
+            [Frame::complete(b"abcd".to_vec())]
+        );
+    }
+
+    #[test]
+    fn length_prefixed_split_across_reads() {
+        let framer = LengthPrefixed::new(LengthWidth::U16, Endianness::Big);
+        let mut buffer = FrameBuffer::new();
+        assert!(frames(&framer, &mut buffer, &[0]).is_empty());
+        assert!(frames(&framer, &mut buffer, &[3, b'a', b'b']).is_empty());
+        assert_eq!(
+            frames(&framer, &mut buffer, &[b'c', 0, 1, b'd']),
+            [
+                Frame::complete(b"abc".to_vec()),
+                Frame::complete(b"d".to_vec())
+            ]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn length_prefixed_little_endian() {
+        let framer = LengthPrefixed::new(LengthWidth::U32, Endianness::Little);
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            frames(&framer, &mut buffer, &[2, 0, 0, 0, b'h', b'i']),
+            [Frame::complete(b"hi".to_vec())]
+        );
+    }
+
+    #[test]
+    fn length_prefixed_oversized_is_chunked_and_stays_in_sync() {
+        let framer = LengthPrefixed::new(LengthWidth::U8, Endianness::Big).max_frame_size(2);
+        let mut buffer = FrameBuffer::new();
+        assert_eq!(
+            frames(&framer, &mut buffer, &[5, b'a', b'b', b'c']),
+            [Frame::truncated(b"ab".to_vec())]
+        );
+        assert_eq!(buffer.bytes, [3, b'c']);
+        assert_eq!(
+            frames(&framer, &mut buffer, &[b'd', b'e', 1, b'f']),
+            [
+                Frame::truncated(b"cd".to_vec()),
+                Frame::truncated(b"e".to_vec()),
+                Frame::complete(b"f".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn length_prefixed_huge_length_does_not_buffer_everything() {
+        let framer = LengthPrefixed::new(LengthWidth::U32, Endianness::Big).max_frame_size(4);
+        let mut buffer = FrameBuffer::new();
+        let mut bytes = vec![0xff, 0xff, 0xff, 0xff];
+        bytes.extend_from_slice(&[0; 10]);
+        let emitted = frames(&framer, &mut buffer, &bytes);
+        assert_eq!(emitted.len(), 2);
+        assert!(emitted.iter().all(|frame| frame.truncated));
+        assert_eq!(buffer.bytes.len(), 4 + 2);
+    }
+}
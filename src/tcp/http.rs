@@ -0,0 +1,480 @@
+use super::framer::FrameBuffer;
+use super::stream::Stream;
+use super::{event, Client, Event, Proxy, Session};
+use crate::address::Address;
+use crate::http::{self, Body, Http, RequestHead, ResponseHead};
+use crate::net;
+use crate::Direction;
+use std::sync::Arc;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
+
+type ParseHead<T> = fn(&[u8]) -> io::Result<Option<(T, usize)>>;
+
+#[derive(Debug)]
+pub(super) struct Connection {
+    pub(super) stream: Stream,
+    pub(super) buffer: Vec<u8>,
+}
+
+impl Connection {
+    pub(super) fn new(stream: Stream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    async fn fill(&mut self, buffer_size: usize) -> io::Result<usize> {
+        let len = self.buffer.len();
+        self.buffer.resize(len + buffer_size, 0);
+        let result = self.stream.read(&mut self.buffer[len..]).await;
+        self.buffer.truncate(len + *result.as_ref().unwrap_or(&0));
+        result
+    }
+
+    async fn fill_or_eof(&mut self, buffer_size: usize) -> io::Result<()> {
+        match self.fill(buffer_size).await? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) async fn read_head<T>(
+        &mut self,
+        buffer_size: usize,
+        parse: ParseHead<T>,
+    ) -> io::Result<Option<(T, Vec<u8>)>> {
+        loop {
+            if !self.buffer.is_empty() {
+                if let Some((head, len)) = parse(&self.buffer)? {
+                    let raw = self.buffer.drain(..len).collect();
+                    return Ok(Some((head, raw)));
+                }
+                if self.buffer.len() > http::MAX_HEAD {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "HTTP head too large",
+                    ));
+                }
+            }
+            if self.fill(buffer_size).await? == 0 {
+                return match self.buffer.is_empty() {
+                    true => Ok(None),
+                    false => Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Upstream {
+    route: Option<usize>,
+    connection: Connection,
+    session: Session,
+    pending: [FrameBuffer; 2],
+}
+
+impl Upstream {
+    fn pending(&mut self, direction: Direction) -> &mut FrameBuffer {
+        match direction {
+            Direction::Upload => &mut self.pending[0],
+            Direction::Download => &mut self.pending[1],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BodyCapture {
+    size: u64,
+    bytes: Option<Vec<u8>>,
+    limit: usize,
+}
+
+impl BodyCapture {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            size: 0,
+            bytes: limit.map(|_| Vec::new()),
+            limit: limit.unwrap_or_default(),
+        }
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        self.size += bytes.len() as u64;
+        if let Some(captured) = &mut self.bytes {
+            let len = bytes.len().min(self.limit - captured.len());
+            captured.extend_from_slice(&bytes[..len]);
+        }
+    }
+}
+
+fn header_strings(headers: &[(String, Vec<u8>)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.clone(), String::from_utf8_lossy(value).into_owned()))
+        .collect()
+}
+
+enum Exchange {
+    KeepAlive,
+    Close,
+    Upgrade,
+}
+
+impl Proxy {
+    pub(super) async fn serve_http(
+        self: &Arc<Self>,
+        http: &Http,
+        client_stream: Stream,
+        client: &Client,
+        default_remote_addrs: &[Address],
+    ) {
+        let secure = matches!(client_stream, Stream::TlsServer(_));
+        let mut downstream = Connection::new(client_stream);
+        let mut upstream: Option<Upstream> = None;
+        loop {
+            let head = net::with_timeout(
+                self.handshake_timeout,
+                downstream.read_head(self.buffer_size, RequestHead::parse),
+            )
+            .await;
+            let started_at = Instant::now();
+            let mut request = match head {
+                Ok(Some((request, _))) => request,
+                Ok(None) => break,
+                Err(error)
+                    if error.kind() == io::ErrorKind::TimedOut && downstream.buffer.is_empty() =>
+                {
+                    break
+                }
+                Err(error) => {
+                    let response = match error.kind() {
+                        io::ErrorKind::InvalidData => {
+                            Some(http::error_response(400, "Bad Request"))
+                        }
+                        io::ErrorKind::TimedOut => {
+                            Some(http::error_response(408, "Request Timeout"))
+                        }
+                        _ => None,
+                    };
+                    if let Some(response) = response {
+                        let _ = downstream.stream.write_all(&response).await;
+                    }
+                    self.send_event(Event::from(event::MessageError {
+                        from_addr: client.addr.clone(),
+                        local_addr: self.local_addr(),
+                        to_addr: upstream.as_ref().map_or(client.destination.clone(), |u| {
+                            u.session.remote_addr.clone()
+                        }),
+                        error,
+                    }))
+                    .await;
+                    break;
+                }
+            };
+
+            let host = request.host();
+            let (route, remote_addrs) = match http.route(host.as_deref(), request.path()) {
+                Some((route, remote_addrs)) => (Some(route), remote_addrs),
+                None => (None, default_remote_addrs),
+            };
+            if upstream.as_ref().is_some_and(|u| u.route != route) {
+                if let Some(upstream) = upstream.take() {
+                    self.close_upstream(upstream).await;
+                }
+            }
+            if upstream.is_none() {
+                let Some((remote_stream, remote_addr)) =
+                    self.open_remote(remote_addrs, client).await
+                else {
+                    let response = http::error_response(502, "Bad Gateway");
+                    let _ = downstream.stream.write_all(&response).await;
+                    break;
+                };
+                let session = self
+                    .open_session(client, &downstream.stream, &remote_stream, remote_addr)
+                    .await;
+                upstream = Some(Upstream {
+                    route,
+                    connection: Connection::new(remote_stream),
+                    session,
+                    pending: Default::default(),
+                });
+            }
+            let Some(current) = upstream.as_mut() else {
+                break;
+            };
+
+            request.use_origin_form();
+            request.strip_hop_by_hop();
+            http.add_forwarding_headers(&mut request, &client.addr, secure);
+            match self
+                .exchange(http, &request, started_at, &mut downstream, current)
+                .await
+            {
+                Ok(Exchange::KeepAlive) => {}
+                Ok(Exchange::Close) => break,
+                Ok(Exchange::Upgrade) => {
+                    let Some(upstream) = upstream.take() else {
+                        break;
+                    };
+                    self.upgrade(downstream, upstream).await;
+                    return;
+                }
+                Err(error) => {
+                    let (from_addr, to_addr) = current.session.endpoints(Direction::Upload);
+                    self.send_event(Event::from(event::MessageError {
+                        from_addr,
+                        local_addr: self.local_addr(),
+                        to_addr,
+                        error,
+                    }))
+                    .await;
+                    break;
+                }
+            }
+        }
+        if let Some(upstream) = upstream {
+            self.close_upstream(upstream).await;
+        }
+        let _ = downstream.stream.shutdown().await;
+    }
+
+    async fn exchange(
+        &self,
+        http: &Http,
+        request: &RequestHead,
+        started_at: Instant,
+        downstream: &mut Connection,
+        upstream: &mut Upstream,
+    ) -> io::Result<Exchange> {
+        let limit = http.body_capture_limit();
+        let request_body = request.body()?;
+        let mut keep_alive = request.keep_alive();
+        let mut upload = Leg::new(downstream, upstream, Direction::Upload);
+        upload.send(self, &request.encode()).await?;
+        let mut request_capture = BodyCapture::new(limit);
+        let mut body_sent = !request.expects_continue();
+        if body_sent {
+            request_capture = upload.forward_body(self, request_body, limit).await?;
+        }
+        loop {
+            let head = upstream
+                .connection
+                .read_head(self.buffer_size, ResponseHead::parse)
+                .await?;
+            let Some((mut response, _)) = head else {
+                let response = http::error_response(502, "Bad Gateway");
+                let _ = downstream.stream.write_all(&response).await;
+                return Ok(Exchange::Close);
+            };
+            let time_to_first_byte = started_at.elapsed();
+            response.strip_hop_by_hop();
+            Leg::new(downstream, upstream, Direction::Download)
+                .send(self, &response.encode())
+                .await?;
+            if response.is_informational() && response.status != 101 {
+                if response.status == 100 && !body_sent {
+                    request_capture = Leg::new(downstream, upstream, Direction::Upload)
+                        .forward_body(self, request_body, limit)
+                        .await?;
+                    body_sent = true;
+                }
+                continue;
+            }
+            let response_body = response.body(&request.method)?;
+            keep_alive &= response.keep_alive() && response_body != Body::UntilClose;
+            if !body_sent && request_body != Body::Empty {
+                keep_alive = false;
+            }
+            let response_capture = Leg::new(downstream, upstream, Direction::Download)
+                .forward_body(self, response_body, limit)
+                .await?;
+            self.send_event(Event::from(event::HttpExchange {
+                client_addr: upstream.session.client_addr.clone(),
+                local_addr: self.local_addr(),
+                remote_addr: upstream.session.remote_addr.clone(),
+                identity: upstream.session.identity.clone(),
+                method: request.method.clone(),
+                path: request.target.clone(),
+                status: response.status,
+                request_headers: header_strings(&request.headers),
+                response_headers: header_strings(&response.headers),
+                request_body_size: request_capture.size,
+                response_body_size: response_capture.size,
+                request_body: request_capture.bytes,
+                response_body: response_capture.bytes,
+                time_to_first_byte,
+                duration: started_at.elapsed(),
+            }))
+            .await;
+            return Ok(match (response.status, keep_alive) {
+                (101, _) => Exchange::Upgrade,
+                (_, true) => Exchange::KeepAlive,
+                (_, false) => Exchange::Close,
+            });
+        }
+    }
+
+    async fn upgrade(self: &Arc<Self>, mut downstream: Connection, mut upstream: Upstream) {
+        let leftovers = [
+            (Direction::Upload, std::mem::take(&mut downstream.buffer)),
+            (
+                Direction::Download,
+                std::mem::take(&mut upstream.connection.buffer),
+            ),
+        ];
+        for (direction, bytes) in leftovers {
+            if !bytes.is_empty() {
+                let _ = Leg::new(&mut downstream, &mut upstream, direction)
+                    .send(self, &bytes)
+                    .await;
+            }
+        }
+        for direction in [Direction::Upload, Direction::Download] {
+            let pending = std::mem::take(upstream.pending(direction));
+            self.flush_frames(direction, &upstream.session, pending)
+                .await;
+        }
+        self.forward(
+            downstream.stream,
+            upstream.connection.stream,
+            upstream.session,
+        )
+        .await;
+    }
+
+    async fn close_upstream(&self, mut upstream: Upstream) {
+        for direction in [Direction::Upload, Direction::Download] {
+            let pending = std::mem::take(upstream.pending(direction));
+            self.flush_frames(direction, &upstream.session, pending)
+                .await;
+            for flow in upstream.session.flows(direction) {
+                flow.close(direction);
+            }
+        }
+        let _ = upstream.connection.stream.shutdown().await;
+        self.close_session(&upstream.session).await;
+    }
+}
+
+struct Leg<'a> {
+    source: &'a mut Connection,
+    sink: &'a mut Stream,
+    session: &'a Session,
+    pending: &'a mut FrameBuffer,
+    direction: Direction,
+    body: BodyCapture,
+}
+
+impl<'a> Leg<'a> {
+    fn new(
+        downstream: &'a mut Connection,
+        upstream: &'a mut Upstream,
+        direction: Direction,
+    ) -> Self {
+        let Upstream {
+            connection,
+            session,
+            pending: [upload, download],
+            ..
+        } = upstream;
+        let (source, sink, pending) = match direction {
+            Direction::Upload => (downstream, &mut connection.stream, upload),
+            Direction::Download => (connection, &mut downstream.stream, download),
+        };
+        Self {
+            source,
+            sink,
+            session,
+            pending,
+            direction,
+            body: BodyCapture::default(),
+        }
+    }
+
+    async fn send(&mut self, proxy: &Proxy, bytes: &[u8]) -> io::Result<()> {
+        proxy
+            .transfer(self.sink, bytes, self.direction, self.session, self.pending)
+            .await
+    }
+
+    async fn forward_buffered(&mut self, proxy: &Proxy, len: usize, body: bool) -> io::Result<()> {
+        let bytes: Vec<u8> = self.source.buffer.drain(..len).collect();
+        if body {
+            self.body.record(&bytes);
+        }
+        self.send(proxy, &bytes).await
+    }
+
+    async fn forward_exact(
+        &mut self,
+        proxy: &Proxy,
+        mut remaining: u64,
+        body: bool,
+    ) -> io::Result<()> {
+        while remaining > 0 {
+            if self.source.buffer.is_empty() {
+                self.source.fill_or_eof(proxy.buffer_size).await?;
+            }
+            let len = self.source.buffer.len().min(remaining as usize);
+            self.forward_buffered(proxy, len, body).await?;
+            remaining -= len as u64;
+        }
+        Ok(())
+    }
+
+    async fn forward_line(&mut self, proxy: &Proxy) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(end) = self.source.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line = self.source.buffer[..end].to_vec();
+                self.forward_buffered(proxy, end + 2, false).await?;
+                return Ok(line);
+            }
+            if self.source.buffer.len() > http::MAX_HEAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunk line too long",
+                ));
+            }
+            self.source.fill_or_eof(proxy.buffer_size).await?;
+        }
+    }
+
+    async fn forward_chunked(&mut self, proxy: &Proxy) -> io::Result<()> {
+        loop {
+            let size = http::parse_chunk_size(&self.forward_line(proxy).await?)?;
+            if size == 0 {
+                while !self.forward_line(proxy).await?.is_empty() {}
+                return Ok(());
+            }
+            self.forward_exact(proxy, size, true).await?;
+            self.forward_exact(proxy, 2, false).await?;
+        }
+    }
+
+    async fn forward_body(
+        &mut self,
+        proxy: &Proxy,
+        body: Body,
+        limit: Option<usize>,
+    ) -> io::Result<BodyCapture> {
+        self.body = BodyCapture::new(limit);
+        match body {
+            Body::Empty => {}
+            Body::Length(len) => self.forward_exact(proxy, len, true).await?,
+            Body::Chunked => self.forward_chunked(proxy).await?,
+            Body::UntilClose => loop {
+                if self.source.buffer.is_empty() && self.source.fill(proxy.buffer_size).await? == 0
+                {
+                    break;
+                }
+                self.forward_buffered(proxy, self.source.buffer.len(), true)
+                    .await?;
+            },
+        }
+        Ok(std::mem::take(&mut self.body))
+    }
+}
@@ -1,22 +1,112 @@
+mod admission;
 mod builder;
+mod connect;
 mod event;
+pub mod framer;
+mod http;
+mod replay;
+mod socks;
+mod stream;
 
-use std::net::SocketAddr;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tokio_rustls::TlsAcceptor;
 
+use self::admission::AdmissionHook;
+use self::framer::{FrameBuffer, Framer};
+use self::stream::Stream;
+use crate::acl::{AccessList, Cidr};
+use crate::address::Address;
+use crate::capture::{Capture, TcpFlow};
+use crate::chain::{self, UpstreamProxy};
+use crate::fault::FaultInjector;
+use crate::http::{ConnectConfig, Http};
+use crate::limit::{Buckets, KeyedBuckets, Limits};
+use crate::net::{self, Listener, Socket};
+use crate::proxy_protocol::{self, Header, ProxyProtocol, ProxyProtocolMode, Transport};
+use crate::record::{Protocol, Recorder};
+use crate::sni::{self, ClientHello, SniRoutes};
+use crate::socks::Socks5Config;
+use crate::tls::{ClientIdentity, TlsConnector};
+use crate::transport::Connector;
+use crate::Direction;
+
+pub use self::admission::Admission;
 pub use self::builder::ProxyBuilder;
 pub use self::event::Event;
+pub use self::replay::Replay;
+
+#[derive(Debug)]
+struct Client {
+    addr: Address,
+    destination: Address,
+    identity: Option<ClientIdentity>,
+    client_hello: ClientHello,
+    target: Option<String>,
+}
+
+#[derive(Debug)]
+struct Session {
+    client_addr: Address,
+    remote_addr: Address,
+    identity: Option<ClientIdentity>,
+    buckets: Vec<Arc<Buckets>>,
+    recording: Option<u64>,
+    flows: Option<[TcpFlow; 2]>,
+    reset: AtomicBool,
+}
+
+impl Session {
+    fn endpoints(&self, direction: Direction) -> (Address, Address) {
+        match direction {
+            Direction::Upload => (self.client_addr.clone(), self.remote_addr.clone()),
+            Direction::Download => (self.remote_addr.clone(), self.client_addr.clone()),
+        }
+    }
+
+    fn flows(&self, direction: Direction) -> Vec<&TcpFlow> {
+        let mut flows: Vec<&TcpFlow> = self.flows.iter().flatten().collect();
+        if direction == Direction::Download {
+            flows.reverse();
+        }
+        flows
+    }
+}
 
 #[derive(Debug)]
 pub struct Proxy {
-    remote_addrs: Vec<SocketAddr>,
+    remote_addrs: Vec<Address>,
+    sni_routes: SniRoutes,
     buffer_size: usize,
+    framer: Arc<dyn Framer>,
+    access_list: AccessList,
+    connection_limits: Limits,
+    client_buckets: KeyedBuckets<IpAddr>,
+    global_buckets: Arc<Buckets>,
+    faults: FaultInjector,
+    recorder: Option<Recorder>,
+    capture: Option<Capture>,
+    proxy_protocol: Option<ProxyProtocol>,
+    accept_proxy_protocol: Option<ProxyProtocolMode>,
+    trusted_proxies: Vec<Cidr>,
+    handshake_timeout: Duration,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    tls_connector: Option<TlsConnector>,
+    admission: Option<AdmissionHook>,
+    http: Option<Http>,
+    connect: Option<ConnectConfig>,
+    socks5: Option<Socks5Config>,
+    upstream_proxies: Vec<UpstreamProxy>,
+    connector: Option<Box<dyn Connector>>,
 
-    listener: TcpListener,
+    listener: Listener,
+    local_addr: Address,
     event_sender: Option<mpsc::Sender<Event>>,
 }
 
@@ -25,64 +115,292 @@ impl Proxy {
         ProxyBuilder::new()
     }
 
-    pub async fn run(self) {
+    pub fn access_list(&self) -> AccessList {
+        self.access_list.clone()
+    }
+
+    pub fn faults(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+
+    pub fn local_addr(&self) -> Address {
+        self.local_addr.clone()
+    }
+
+    pub async fn run(self) -> io::Result<()> {
+        self.send_event(Event::from(event::Listening {
+            local_addr: self.local_addr(),
+        }))
+        .await;
         let this = Arc::new(self);
+        let mut connections = JoinSet::new();
         loop {
-            let Some((client_stream, client_addr)) = this.accept_client().await else {
-                break;
-            };
-            let this = this.clone();
-            tokio::spawn(async move {
-                let Some((remote_stream, remote_addr)) = this.connect_remote().await else {
-                    return;
-                };
+            tokio::select! {
+                accepted = this.accept_client() => {
+                    let (client_stream, client_addr) = accepted?;
+                    connections.spawn(this.clone().handle(client_stream, client_addr));
+                }
+                Some(_) = connections.join_next() => {}
+            }
+        }
+    }
 
-                this.send_event(Event::from(event::Connection {
-                    client_addr: client_addr.clone(),
-                    local_addr: this.local_addr(),
-                    remote_addr: remote_addr.clone(),
+    async fn handle(self: Arc<Self>, mut client_stream: Socket, mut client_addr: Address) {
+        let mut destination = client_stream
+            .local_addr()
+            .unwrap_or_else(|_| self.local_addr());
+        let Ok(header) = self
+            .accept_proxy_header(&mut client_stream, &client_addr)
+            .await
+        else {
+            return;
+        };
+        if let Some(header) = header {
+            client_addr = header.source.map(Address::Inet).unwrap_or(client_addr);
+            destination = header.destination.map(Address::Inet).unwrap_or(destination);
+        }
+        if client_addr
+            .ip()
+            .is_some_and(|ip| !self.access_list.is_allowed(ip))
+        {
+            self.send_event(Event::from(event::Rejected {
+                client_addr,
+                local_addr: self.local_addr(),
+            }))
+            .await;
+            return;
+        }
+        let Ok(client_hello) = self.peek_client_hello(&client_stream).await else {
+            return;
+        };
+        let remote_addrs = client_hello
+            .server_name
+            .as_deref()
+            .and_then(|server_name| self.sni_routes.get(server_name))
+            .unwrap_or(&self.remote_addrs);
+        let Some(client_stream) = self.accept_tls(client_stream, &client_addr).await else {
+            return;
+        };
+        let identity = client_stream.client_identity();
+        if let Some(admission) = &self.admission {
+            let admitted = admission.admit(&Admission {
+                client_addr: client_addr.clone(),
+                local_addr: self.local_addr(),
+                identity: identity.clone(),
+            });
+            if !admitted {
+                self.send_event(Event::from(event::Rejected {
+                    client_addr,
+                    local_addr: self.local_addr(),
                 }))
                 .await;
+                return;
+            }
+        }
+        let client = Client {
+            addr: client_addr,
+            destination,
+            identity,
+            client_hello,
+            target: None,
+        };
+        if let Some(connect) = &self.connect {
+            self.serve_connect(connect, client_stream, client).await;
+            return;
+        }
+        if let Some(socks5) = &self.socks5 {
+            self.serve_socks(socks5, client_stream, client).await;
+            return;
+        }
+        if let Some(http) = &self.http {
+            self.serve_http(http, client_stream, &client, remote_addrs)
+                .await;
+            return;
+        }
+        let Some((remote_stream, remote_addr)) = self.open_remote(remote_addrs, &client).await
+        else {
+            return;
+        };
+        let session = self
+            .open_session(&client, &client_stream, &remote_stream, remote_addr)
+            .await;
+        self.forward(client_stream, remote_stream, session).await;
+    }
 
-                let (client_reader, client_writer) = client_stream.into_split();
-                let (remote_reader, remote_writer) = remote_stream.into_split();
-                let (close_sender, close_receiver) = broadcast::channel(2);
+    async fn open_remote(
+        &self,
+        remote_addrs: &[Address],
+        client: &Client,
+    ) -> Option<(Stream, Address)> {
+        let connect_delay = self.faults.connect_delay();
+        if !connect_delay.is_zero() {
+            sleep(connect_delay).await;
+        }
+        let (mut remote_stream, remote_addr) = self.connect_remote(remote_addrs).await?;
+        if let Some(proxy_protocol) = &self.proxy_protocol {
+            let header = match (client.addr.inet(), client.destination.inet()) {
+                (Some(source), Some(destination)) => {
+                    proxy_protocol.encode(Transport::Stream, source, destination)
+                }
+                _ => proxy_protocol.encode_local(),
+            };
+            if let Err(error) = remote_stream.write_all(&header).await {
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error,
+                }))
+                .await;
+                return None;
+            }
+        }
+        let remote_stream = self.connect_tls(remote_stream, &remote_addr).await?;
+        Some((remote_stream, remote_addr))
+    }
 
-                let client_handle = tokio::spawn({
-                    let this = this.clone();
-                    let close_sender = close_sender.clone();
-                    let close_receiver = close_sender.subscribe();
-                    async move {
-                        this.pipe(client_reader, remote_writer, close_receiver)
-                            .await;
-                        let _ = close_sender.send(());
-                    }
-                });
-                let remote_handle = tokio::spawn({
-                    let this = this.clone();
-                    async move {
-                        this.pipe(remote_reader, client_writer, close_receiver)
-                            .await;
-                        let _ = close_sender.send(());
-                    }
-                });
+    async fn open_session(
+        &self,
+        client: &Client,
+        client_stream: &Stream,
+        remote_stream: &Stream,
+        remote_addr: Address,
+    ) -> Session {
+        let client_addr = client.addr.clone();
+        self.send_event(Event::from(event::Connection {
+            client_addr: client_addr.clone(),
+            local_addr: self.local_addr(),
+            remote_addr: remote_addr.clone(),
+            identity: client.identity.clone(),
+            server_name: client.client_hello.server_name.clone(),
+            alpn_protocols: client.client_hello.alpn_protocols.clone(),
+            target: client.target.clone(),
+        }))
+        .await;
 
-                client_handle.await.unwrap();
-                remote_handle.await.unwrap();
+        let client_buckets = client_addr.ip().and_then(|ip| self.client_buckets.get(ip));
+        let recording = self
+            .recorder
+            .as_ref()
+            .map(|recorder| recorder.open(Protocol::Tcp, &client_addr));
+        Session {
+            client_addr,
+            remote_addr,
+            identity: client.identity.clone(),
+            buckets: [
+                Some(Arc::new(Buckets::new(self.connection_limits))),
+                client_buckets,
+                Some(self.global_buckets.clone()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            recording,
+            flows: self.capture.as_ref().and_then(|capture| {
+                let client_tcp = client_stream.socket().tcp()?;
+                let remote_tcp = remote_stream.socket().tcp()?;
+                let peer_addr = client_tcp.peer_addr().ok()?;
+                let accepted_addr = client_tcp.local_addr().ok()?;
+                let upstream_addr = remote_tcp.local_addr().unwrap_or(accepted_addr);
+                let remote_addr = remote_tcp.peer_addr().ok()?;
+                Some([
+                    capture.tcp_flow(peer_addr, accepted_addr),
+                    capture.tcp_flow(upstream_addr, remote_addr),
+                ])
+            }),
+            reset: AtomicBool::new(false),
+        }
+    }
 
-                this.send_event(Event::from(event::Disconnection {
-                    client_addr,
-                    local_addr: this.local_addr(),
-                    remote_addr,
+    async fn close_session(&self, session: &Session) {
+        if let (Some(recorder), Some(recording)) = (&self.recorder, session.recording) {
+            recorder.close(recording);
+        }
+        self.send_event(Event::from(event::Disconnection {
+            client_addr: session.client_addr.clone(),
+            local_addr: self.local_addr(),
+            remote_addr: session.remote_addr.clone(),
+            identity: session.identity.clone(),
+        }))
+        .await;
+    }
+
+    async fn forward(
+        self: &Arc<Self>,
+        client_stream: Stream,
+        remote_stream: Stream,
+        session: Session,
+    ) {
+        let (client_reader, client_writer) = io::split(client_stream);
+        let (remote_reader, remote_writer) = io::split(remote_stream);
+        let (close_sender, close_receiver) = broadcast::channel(2);
+
+        let ((client_reader, remote_writer), (remote_reader, client_writer)) = tokio::join!(
+            async {
+                let halves = self
+                    .pipe(
+                        client_reader,
+                        remote_writer,
+                        close_sender.subscribe(),
+                        Direction::Upload,
+                        &session,
+                    )
+                    .await;
+                let _ = close_sender.send(());
+                halves
+            },
+            async {
+                let halves = self
+                    .pipe(
+                        remote_reader,
+                        client_writer,
+                        close_receiver,
+                        Direction::Download,
+                        &session,
+                    )
+                    .await;
+                let _ = close_sender.send(());
+                halves
+            },
+        );
+        for mut stream in [
+            client_reader.unsplit(client_writer),
+            remote_reader.unsplit(remote_writer),
+        ] {
+            if session.reset.load(Ordering::Relaxed) {
+                let _ = stream.socket().set_linger(Some(Duration::ZERO));
+            } else {
+                let _ = stream.shutdown().await;
+            }
+        }
+
+        self.close_session(&session).await;
+    }
+
+    async fn accept_tls(&self, stream: Socket, client_addr: &Address) -> Option<Stream> {
+        let Some(tls_config) = &self.tls_config else {
+            return Some(Stream::Plain(stream));
+        };
+        let accept = TlsAcceptor::from(tls_config.clone()).accept(stream);
+        match net::with_timeout(self.handshake_timeout, accept).await {
+            Ok(stream) => Some(Stream::TlsServer(Box::new(stream))),
+            Err(error) => {
+                self.send_event(Event::from(event::TlsError {
+                    client_addr: client_addr.clone(),
+                    local_addr: self.local_addr(),
+                    error,
                 }))
                 .await;
-            });
+                None
+            }
         }
     }
 
-    async fn accept_client(&self) -> Option<(TcpStream, SocketAddr)> {
-        match self.listener.accept().await {
-            Ok(result) => Some(result),
+    async fn connect_tls(&self, stream: Socket, remote_addr: &Address) -> Option<Stream> {
+        let Some(tls_connector) = &self.tls_connector else {
+            return Some(Stream::Plain(stream));
+        };
+        let connect = tls_connector.connect(stream, remote_addr);
+        match net::with_timeout(self.handshake_timeout, connect).await {
+            Ok(stream) => Some(Stream::TlsClient(Box::new(stream))),
             Err(error) => {
                 let local_addr = self.local_addr();
                 self.send_event(Event::from(event::ConnectionError { local_addr, error }))
@@ -92,31 +410,104 @@ impl Proxy {
         }
     }
 
-    async fn connect_remote(&self) -> Option<(TcpStream, SocketAddr)> {
-        match TcpStream::connect(&*self.remote_addrs).await {
-            Ok(result) => {
-                let socket_addr = result.peer_addr().unwrap();
-                Some((result, socket_addr))
-            }
+    async fn peek_client_hello(&self, stream: &Socket) -> Result<ClientHello, ()> {
+        let Some(stream) = stream.tcp().filter(|_| !self.sni_routes.is_empty()) else {
+            return Ok(ClientHello::default());
+        };
+        match sni::peek_client_hello(stream, self.handshake_timeout).await {
+            Ok(client_hello) => Ok(client_hello),
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => Ok(ClientHello::default()),
             Err(error) => {
                 let local_addr = self.local_addr();
                 self.send_event(Event::from(event::ConnectionError { local_addr, error }))
                     .await;
-                None
+                Err(())
+            }
+        }
+    }
+
+    async fn accept_client(&self) -> io::Result<(Socket, Address)> {
+        match self.listener.accept().await {
+            Ok(result) => Ok(result),
+            Err(error) => {
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error: io::Error::new(error.kind(), error.to_string()),
+                }))
+                .await;
+                Err(error)
             }
         }
     }
 
+    async fn accept_proxy_header(
+        &self,
+        stream: &mut Socket,
+        peer_addr: &Address,
+    ) -> Result<Option<Header>, ()> {
+        let (Some(mode), Some(stream)) = (self.accept_proxy_protocol, stream.tcp_mut()) else {
+            return Ok(None);
+        };
+        let is_trusted = peer_addr
+            .ip()
+            .is_some_and(|ip| self.trusted_proxies.iter().any(|cidr| cidr.contains(ip)));
+        if !is_trusted {
+            if mode == ProxyProtocolMode::Optional {
+                return Ok(None);
+            }
+            self.send_event(Event::from(event::Rejected {
+                client_addr: peer_addr.clone(),
+                local_addr: self.local_addr(),
+            }))
+            .await;
+            return Err(());
+        }
+        let error = match proxy_protocol::read_header(stream, self.handshake_timeout).await {
+            Ok(Some(header)) => return Ok(Some(header)),
+            Ok(None) if mode == ProxyProtocolMode::Optional => return Ok(None),
+            Ok(None) => io::Error::new(io::ErrorKind::InvalidData, "missing PROXY protocol header"),
+            Err(error) => error,
+        };
+        let local_addr = self.local_addr();
+        self.send_event(Event::from(event::ConnectionError { local_addr, error }))
+            .await;
+        Err(())
+    }
+
+    async fn connect_remote(&self, remote_addrs: &[Address]) -> Option<(Socket, Address)> {
+        let mut last_error = None;
+        for remote_addr in remote_addrs {
+            match chain::connect(
+                self.connector.as_deref(),
+                &self.upstream_proxies,
+                remote_addr,
+            )
+            .await
+            {
+                Ok(stream) => return Some((stream, remote_addr.clone())),
+                Err(error) => last_error = Some(error),
+            }
+        }
+        let error = last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no remote addresses"));
+        let local_addr = self.local_addr();
+        self.send_event(Event::from(event::ConnectionError { local_addr, error }))
+            .await;
+        None
+    }
+
     async fn pipe(
         &self,
-        mut reader: OwnedReadHalf,
-        mut writer: OwnedWriteHalf,
+        mut reader: ReadHalf<Stream>,
+        mut writer: WriteHalf<Stream>,
         mut close_receiver: broadcast::Receiver<()>,
-    ) {
+        direction: Direction,
+        session: &Session,
+    ) -> (ReadHalf<Stream>, WriteHalf<Stream>) {
         let mut buffer = vec![0; self.buffer_size];
-        let from_addr = reader.peer_addr().unwrap();
-        let local_addr = self.local_addr();
-        let to_addr = writer.peer_addr().unwrap();
+        let mut pending = FrameBuffer::new();
+        let mut forwarded = 0;
+        let (from_addr, to_addr) = session.endpoints(direction);
         loop {
             tokio::select! {
                 n = reader.read(&mut buffer) => {
@@ -137,25 +528,104 @@ impl Proxy {
                     if n == 0 {
                         break;
                     }
-                    let message = String::from_utf8_lossy(&buffer[0..n]).to_string();
-                    let _ = writer.write(&buffer[0..n]).await;
-                    self.send_event(Event::from(event::Message {
-                        from_addr,
-                        local_addr,
-                        to_addr,
-                        message,
-                    }))
-                    .await;
+                    let mut n = n;
+                    let mut truncated = false;
+                    if let Some(limit) = self.faults.truncate_after() {
+                        let remaining = limit.saturating_sub(forwarded);
+                        if n as u64 >= remaining {
+                            n = remaining as usize;
+                            truncated = true;
+                        }
+                    }
+                    if self.faults.should_reset() {
+                        session.reset.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    if let Err(error) = self
+                        .transfer(&mut writer, &buffer[0..n], direction, session, &mut pending)
+                        .await
+                    {
+                        let local_addr = self.local_addr();
+                        self.send_event(Event::from(event::MessageError {
+                            from_addr,
+                            local_addr,
+                            to_addr,
+                            error,
+                        }))
+                        .await;
+                        break;
+                    }
+                    forwarded += n as u64;
+                    if truncated {
+                        break;
+                    }
                 }
                 _ = close_receiver.recv() => {
                     break;
                 }
             }
         }
+        for flow in session.flows(direction) {
+            flow.close(direction);
+        }
+        self.flush_frames(direction, session, pending).await;
+        (reader, writer)
+    }
+
+    async fn transfer<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        bytes: &[u8],
+        direction: Direction,
+        session: &Session,
+        pending: &mut FrameBuffer,
+    ) -> io::Result<()> {
+        let delay = self.faults.delay(bytes.len());
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        for bucket in session.buckets.iter().filter_map(|b| b.get(direction)) {
+            bucket.acquire(bytes.len() as u64).await;
+        }
+        writer.write_all(bytes).await?;
+        if let (Some(recorder), Some(recording)) = (&self.recorder, session.recording) {
+            recorder.record(recording, direction, bytes);
+        }
+        for flow in session.flows(direction) {
+            flow.data(direction, bytes);
+        }
+        pending.extend_from_slice(bytes);
+        let (from_addr, to_addr) = session.endpoints(direction);
+        while let Some(frame) = self.framer.next_frame(pending) {
+            let message = String::from_utf8_lossy(&frame.bytes).to_string();
+            self.send_event(Event::from(event::Message {
+                from_addr: from_addr.clone(),
+                local_addr: self.local_addr(),
+                to_addr: to_addr.clone(),
+                message,
+                truncated: frame.truncated,
+                identity: session.identity.clone(),
+            }))
+            .await;
+        }
+        Ok(())
     }
 
-    fn local_addr(&self) -> SocketAddr {
-        self.listener.local_addr().unwrap()
+    async fn flush_frames(&self, direction: Direction, session: &Session, pending: FrameBuffer) {
+        if pending.is_empty() {
+            return;
+        }
+        let (from_addr, to_addr) = session.endpoints(direction);
+        let message = String::from_utf8_lossy(&pending.bytes).to_string();
+        self.send_event(Event::from(event::Message {
+            from_addr,
+            local_addr: self.local_addr(),
+            to_addr,
+            message,
+            truncated: true,
+            identity: session.identity.clone(),
+        }))
+        .await;
     }
 
     async fn send_event(&self, event: Event) {
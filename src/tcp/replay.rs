@@ -0,0 +1,154 @@
+use crate::record::{Exchange, Protocol, RecordedConnection, Recording, ReplayMode};
+use crate::Direction;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+#[derive(Debug)]
+pub struct Replay {
+    listener: TcpListener,
+    connections: Vec<RecordedConnection>,
+    mode: ReplayMode,
+}
+
+impl Replay {
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A,
+        recording: &Recording,
+        mode: ReplayMode,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            connections: recording.connections(Protocol::Tcp),
+            mode,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub async fn run(self) {
+        let exchanges: Arc<[Exchange]> = self
+            .connections
+            .iter()
+            .flat_map(RecordedConnection::exchanges)
+            .collect();
+        let mut connections = self.connections.into_iter();
+        while let Ok((stream, _)) = self.listener.accept().await {
+            match self.mode {
+                ReplayMode::ConnectionOrder => {
+                    let Some(connection) = connections.next() else {
+                        continue;
+                    };
+                    tokio::spawn(replay_in_order(stream, connection));
+                }
+                ReplayMode::MatchRequests => {
+                    tokio::spawn(replay_matching(stream, exchanges.clone()));
+                }
+            }
+        }
+    }
+}
+
+async fn replay_in_order(mut stream: TcpStream, connection: RecordedConnection) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    for chunk in connection.chunks {
+        match chunk.direction {
+            Direction::Upload => {
+                buffer.resize(chunk.bytes.len(), 0);
+                stream.read_exact(&mut buffer).await?;
+            }
+            Direction::Download => stream.write_all(&chunk.bytes).await?,
+        }
+    }
+    Ok(())
+}
+
+async fn replay_matching(mut stream: TcpStream, exchanges: Arc<[Exchange]>) -> io::Result<()> {
+    if let Some(greeting) = exchanges.iter().find(|e| e.request.is_empty()) {
+        for response in &greeting.responses {
+            stream.write_all(response).await?;
+        }
+    }
+    let mut buffer = vec![0; 1024];
+    let mut pending = Vec::new();
+    loop {
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&buffer[..n]);
+        loop {
+            let matched = exchanges
+                .iter()
+                .find(|e| !e.request.is_empty() && pending.starts_with(&e.request));
+            if let Some(exchange) = matched {
+                for response in &exchange.responses {
+                    stream.write_all(response).await?;
+                }
+                pending.drain(..exchange.request.len());
+                continue;
+            }
+            if exchanges.iter().any(|e| e.request.starts_with(&pending)) {
+                break;
+            }
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording() -> Recording {
+        let text = "proxy-rs-recording 1\n\
+                    open 0 tcp 127.0.0.1:4000 0\n\
+                    data 0 up 10 70696e67\n\
+                    data 0 down 20 706f6e67\n\
+                    data 0 up 30 627965\n\
+                    data 0 down 40 6f6b\n\
+                    close 0 50\n";
+        Recording::from_reader(text.as_bytes()).unwrap()
+    }
+
+    async fn exchange(stream: &mut TcpStream, request: &[u8], response: &[u8]) {
+        stream.write_all(request).await.unwrap();
+        let mut buffer = vec![0; response.len()];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, response);
+    }
+
+    #[tokio::test]
+    async fn replays_connections_in_order() {
+        let replay = Replay::bind("127.0.0.1:0", &recording(), ReplayMode::ConnectionOrder)
+            .await
+            .unwrap();
+        let addr = replay.local_addr().unwrap();
+        tokio::spawn(replay.run());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        exchange(&mut stream, b"ping", b"pong").await;
+        exchange(&mut stream, b"bye", b"ok").await;
+        let mut buffer = [0; 1];
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn replays_matching_requests() {
+        let replay = Replay::bind("127.0.0.1:0", &recording(), ReplayMode::MatchRequests)
+            .await
+            .unwrap();
+        let addr = replay.local_addr().unwrap();
+        tokio::spawn(replay.run());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        exchange(&mut stream, b"bye", b"ok").await;
+        exchange(&mut stream, b"ping", b"pong").await;
+        stream.write_all(b"unknown").await.unwrap();
+        let mut buffer = [0; 1];
+        assert!(matches!(stream.read(&mut buffer).await, Ok(0) | Err(_)));
+    }
+}
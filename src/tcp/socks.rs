@@ -0,0 +1,472 @@
+use super::stream::Stream;
+use super::{event, Client, Event, Proxy};
+use crate::address::Address;
+use crate::limit::{self, Buckets};
+use crate::net::{self, DatagramSocket};
+use crate::record::Protocol;
+use crate::socks::{self, Socks5Config, Target};
+use crate::udp::{Delivery, Relay};
+use crate::Direction;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+const MAX_DATAGRAM: usize = 65535;
+const MAX_RESOLVED: usize = 256;
+
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+// A UDP ASSOCIATE relay. Replies are only forwarded from targets the client
+// has sent to, and domain targets are resolved once per association.
+#[derive(Debug)]
+struct Association {
+    relay: Relay,
+    relay_addr: Address,
+    client: Client,
+    datagram_addr: Option<Address>,
+    targets: HashSet<SocketAddr>,
+    resolved: HashMap<Target, SocketAddr>,
+    buckets: Vec<Arc<Buckets>>,
+    recording: Option<u64>,
+}
+
+impl Association {
+    fn admit(&self, direction: Direction, len: usize) -> bool {
+        limit::try_acquire_all(
+            self.buckets
+                .iter()
+                .filter_map(|buckets| buckets.get(direction))
+                .map(|bucket| (bucket, len as u64)),
+        )
+    }
+
+    fn is_from_client(&self, from_addr: &Address) -> bool {
+        match &self.datagram_addr {
+            Some(addr) => addr == from_addr,
+            None => self
+                .client
+                .addr
+                .ip()
+                .is_none_or(|ip| Some(ip) == from_addr.ip()),
+        }
+    }
+}
+
+impl Proxy {
+    pub(super) async fn serve_socks(
+        self: &Arc<Self>,
+        socks5: &Socks5Config,
+        mut client_stream: Stream,
+        mut client: Client,
+    ) {
+        let handshake = self.socks_handshake(socks5, &mut client_stream);
+        let request = match net::with_timeout(self.handshake_timeout, handshake).await {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                self.send_event(Event::from(event::Rejected {
+                    client_addr: client.addr.clone(),
+                    local_addr: self.local_addr(),
+                }))
+                .await;
+                return;
+            }
+            Err(error) => {
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error,
+                }))
+                .await;
+                return;
+            }
+        };
+        let (command, address) = request;
+        client.target = Some(address.to_string());
+        match command {
+            socks::COMMAND_CONNECT => self.socks_connect(client_stream, client, address).await,
+            socks::COMMAND_UDP_ASSOCIATE => {
+                self.socks_associate(client_stream, client, address).await
+            }
+            _ => {
+                let reply = socks::reply(socks::REPLY_COMMAND_NOT_SUPPORTED, &self.local_addr());
+                let _ = client_stream.write_all(&reply).await;
+            }
+        }
+    }
+
+    async fn socks_handshake(
+        &self,
+        socks5: &Socks5Config,
+        stream: &mut Stream,
+    ) -> io::Result<Option<(u8, Target)>> {
+        let version = stream.read_u8().await?;
+        if version != socks::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported socks version",
+            ));
+        }
+        let count = stream.read_u8().await?;
+        let mut methods = vec![0; usize::from(count)];
+        stream.read_exact(&mut methods).await?;
+        let method = socks5.method();
+        if !methods.contains(&method) {
+            stream
+                .write_all(&[socks::VERSION, socks::METHOD_NOT_ACCEPTABLE])
+                .await?;
+            return Ok(None);
+        }
+        stream.write_all(&[socks::VERSION, method]).await?;
+
+        if method == socks::METHOD_USERNAME_PASSWORD {
+            if stream.read_u8().await? != socks::AUTH_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unsupported socks auth version",
+                ));
+            }
+            let len = stream.read_u8().await?;
+            let mut username = vec![0; usize::from(len)];
+            stream.read_exact(&mut username).await?;
+            let len = stream.read_u8().await?;
+            let mut password = vec![0; usize::from(len)];
+            stream.read_exact(&mut password).await?;
+            let authenticated = socks5.authenticate(&username, &password);
+            let status = if authenticated { 0x00 } else { 0x01 };
+            stream.write_all(&[socks::AUTH_VERSION, status]).await?;
+            if !authenticated {
+                return Ok(None);
+            }
+        }
+
+        let mut head = [0; 3];
+        stream.read_exact(&mut head).await?;
+        if head[0] != socks::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported socks version",
+            ));
+        }
+        match Target::read(stream).await {
+            Ok(address) => Ok(Some((head[1], address))),
+            Err(error) => {
+                if error.kind() == io::ErrorKind::InvalidData {
+                    let reply =
+                        socks::reply(socks::REPLY_ADDRESS_TYPE_NOT_SUPPORTED, &self.local_addr());
+                    let _ = stream.write_all(&reply).await;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    async fn socks_connect(
+        self: &Arc<Self>,
+        mut client_stream: Stream,
+        client: Client,
+        address: Target,
+    ) {
+        let remote_addrs: Vec<Address> = match address.resolve(self.connector.as_deref()).await {
+            Ok(addrs) if !addrs.is_empty() => addrs.into_iter().map(Address::Inet).collect(),
+            Ok(_) => {
+                let reply = socks::reply(socks::REPLY_HOST_UNREACHABLE, &self.local_addr());
+                let _ = client_stream.write_all(&reply).await;
+                return;
+            }
+            Err(error) => {
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error,
+                }))
+                .await;
+                let reply = socks::reply(socks::REPLY_HOST_UNREACHABLE, &self.local_addr());
+                let _ = client_stream.write_all(&reply).await;
+                return;
+            }
+        };
+        let Some((remote_stream, remote_addr)) = self.open_remote(&remote_addrs, &client).await
+        else {
+            let reply = socks::reply(socks::REPLY_CONNECTION_REFUSED, &self.local_addr());
+            let _ = client_stream.write_all(&reply).await;
+            return;
+        };
+        let bound = remote_stream
+            .socket()
+            .local_addr()
+            .unwrap_or_else(|_| self.local_addr());
+        let reply = socks::reply(socks::REPLY_SUCCEEDED, &bound);
+        if let Err(error) = client_stream.write_all(&reply).await {
+            self.send_event(Event::from(event::ConnectionError {
+                local_addr: self.local_addr(),
+                error,
+            }))
+            .await;
+            return;
+        }
+        let session = self
+            .open_session(&client, &client_stream, &remote_stream, remote_addr)
+            .await;
+        self.forward(client_stream, remote_stream, session).await;
+    }
+
+    async fn socks_associate(
+        self: &Arc<Self>,
+        mut client_stream: Stream,
+        client: Client,
+        address: Target,
+    ) {
+        let bind_ip = client_stream
+            .socket()
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let bind_addr = SocketAddr::new(bind_ip, 0);
+        let socket = match DatagramSocket::bind_udp(&[bind_addr]).await {
+            Ok(socket) => socket,
+            Err(error) => {
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error,
+                }))
+                .await;
+                let reply = socks::reply(socks::REPLY_GENERAL_FAILURE, &self.local_addr());
+                let _ = client_stream.write_all(&reply).await;
+                return;
+            }
+        };
+        let relay_addr = socket.local_addr().unwrap_or(Address::Inet(bind_addr));
+        let reply = socks::reply(socks::REPLY_SUCCEEDED, &relay_addr);
+        if client_stream.write_all(&reply).await.is_err() {
+            return;
+        }
+        self.send_event(Event::from(event::Connection {
+            client_addr: client.addr.clone(),
+            local_addr: self.local_addr(),
+            remote_addr: relay_addr.clone(),
+            identity: client.identity.clone(),
+            server_name: None,
+            alpn_protocols: Vec::new(),
+            target: client.target.clone(),
+        }))
+        .await;
+
+        let client_buckets = client.addr.ip().and_then(|ip| self.client_buckets.get(ip));
+        let recording = self
+            .recorder
+            .as_ref()
+            .map(|recorder| recorder.open(Protocol::Udp, &client.addr));
+        let mut association = Association {
+            relay: Relay::new(socket, self.faults.clone()),
+            relay_addr,
+            datagram_addr: match address {
+                Target::Socket(addr) if !addr.ip().is_unspecified() && addr.port() != 0 => {
+                    Some(Address::Inet(addr))
+                }
+                _ => None,
+            },
+            client,
+            targets: HashSet::new(),
+            resolved: HashMap::new(),
+            buckets: [
+                Some(Arc::new(Buckets::new(self.connection_limits))),
+                client_buckets,
+                Some(self.global_buckets.clone()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            recording,
+        };
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        let mut control = [0; 1];
+        loop {
+            let (len, from_addr) = tokio::select! {
+                received = association.relay.recv_from(&mut buffer) => match received {
+                    Ok(received) => received,
+                    Err(error) => {
+                        self.send_event(Event::from(event::ConnectionError {
+                            local_addr: self.local_addr(),
+                            error,
+                        }))
+                        .await;
+                        break;
+                    }
+                },
+                read = client_stream.read(&mut control) => match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => continue,
+                },
+            };
+            let datagram = &buffer[..len];
+            if association.is_from_client(&from_addr) {
+                association.datagram_addr = Some(from_addr);
+                self.socks_upload(&mut association, datagram).await;
+            } else if let Some(from_addr) = from_addr.inet() {
+                self.socks_download(&mut association, from_addr, datagram)
+                    .await;
+            }
+        }
+
+        if let (Some(recorder), Some(recording)) = (&self.recorder, association.recording) {
+            recorder.close(recording);
+        }
+        self.send_event(Event::from(event::Disconnection {
+            client_addr: association.client.addr,
+            local_addr: self.local_addr(),
+            remote_addr: association.relay_addr,
+            identity: association.client.identity,
+        }))
+        .await;
+    }
+
+    async fn socks_resolve(
+        &self,
+        association: &mut Association,
+        target: Target,
+    ) -> Option<SocketAddr> {
+        if let Target::Socket(addr) = target {
+            return Some(addr);
+        }
+        if let Some(addr) = association.resolved.get(&target) {
+            return Some(*addr);
+        }
+        match target.resolve(self.connector.as_deref()).await {
+            Ok(addrs) => {
+                let addr = *addrs.first()?;
+                if association.resolved.len() >= MAX_RESOLVED {
+                    association.resolved.clear();
+                }
+                association.resolved.insert(target, addr);
+                Some(addr)
+            }
+            Err(error) => {
+                self.send_event(Event::from(event::ConnectionError {
+                    local_addr: self.local_addr(),
+                    error,
+                }))
+                .await;
+                None
+            }
+        }
+    }
+
+    async fn socks_upload(&self, association: &mut Association, datagram: &[u8]) {
+        if datagram.len() < 3 || datagram[2] != 0 {
+            return;
+        }
+        let Ok((target, len)) = Target::decode(&datagram[3..]) else {
+            return;
+        };
+        let payload = &datagram[3 + len..];
+        let Some(remote_addr) = self.socks_resolve(association, target).await else {
+            return;
+        };
+        let from_addr = association.client.addr.clone();
+        let to_addr = Address::Inet(remote_addr);
+        if !association.admit(Direction::Upload, payload.len()) {
+            self.send_event(Event::from(event::RateLimited {
+                from_addr,
+                local_addr: self.local_addr(),
+                to_addr,
+            }))
+            .await;
+            return;
+        }
+        association.targets.insert(canonical(remote_addr));
+        let delivery = association.relay.send_to(payload, &to_addr).await;
+        if let (Some(recorder), Some(recording)) = (&self.recorder, association.recording) {
+            recorder.record(recording, Direction::Upload, payload);
+        }
+        if let (Some(capture), Some(client_addr), Some(relay_addr)) = (
+            &self.capture,
+            association.datagram_addr.as_ref().and_then(Address::inet),
+            association.relay_addr.inet(),
+        ) {
+            capture.udp(client_addr, relay_addr, datagram);
+            if matches!(delivery, Ok(Delivery::Sent | Delivery::Held)) {
+                capture.udp(relay_addr, remote_addr, payload);
+            }
+        }
+        self.report_datagram(association, delivery, from_addr, to_addr, payload)
+            .await;
+    }
+
+    async fn socks_download(
+        &self,
+        association: &mut Association,
+        remote_addr: SocketAddr,
+        payload: &[u8],
+    ) {
+        let Some(datagram_addr) = association.datagram_addr.clone() else {
+            return;
+        };
+        if !association.targets.contains(&canonical(remote_addr)) {
+            return;
+        }
+        let from_addr = Address::Inet(remote_addr);
+        let to_addr = association.client.addr.clone();
+        if !association.admit(Direction::Download, payload.len()) {
+            self.send_event(Event::from(event::RateLimited {
+                from_addr,
+                local_addr: self.local_addr(),
+                to_addr,
+            }))
+            .await;
+            return;
+        }
+        let mut datagram = socks::udp_header(&Target::Socket(remote_addr));
+        datagram.extend_from_slice(payload);
+        let delivery = association.relay.send_to(&datagram, &datagram_addr).await;
+        if let (Some(recorder), Some(recording)) = (&self.recorder, association.recording) {
+            recorder.record(recording, Direction::Download, payload);
+        }
+        if let (Some(capture), Some(client_addr), Some(relay_addr)) = (
+            &self.capture,
+            datagram_addr.inet(),
+            association.relay_addr.inet(),
+        ) {
+            capture.udp(remote_addr, relay_addr, payload);
+            if matches!(delivery, Ok(Delivery::Sent | Delivery::Held)) {
+                capture.udp(relay_addr, client_addr, &datagram);
+            }
+        }
+        self.report_datagram(association, delivery, from_addr, to_addr, payload)
+            .await;
+    }
+
+    async fn report_datagram(
+        &self,
+        association: &Association,
+        delivery: io::Result<Delivery>,
+        from_addr: Address,
+        to_addr: Address,
+        payload: &[u8],
+    ) {
+        match delivery {
+            Ok(Delivery::Sent) => {
+                self.send_event(Event::from(event::Message {
+                    from_addr,
+                    local_addr: self.local_addr(),
+                    to_addr,
+                    message: String::from_utf8_lossy(payload).to_string(),
+                    truncated: false,
+                    identity: association.client.identity.clone(),
+                }))
+                .await;
+            }
+            Ok(Delivery::Dropped | Delivery::Held) => {}
+            Err(error) => {
+                self.send_event(Event::from(event::MessageError {
+                    from_addr,
+                    local_addr: self.local_addr(),
+                    to_addr,
+                    error,
+                }))
+                .await;
+            }
+        }
+    }
+}
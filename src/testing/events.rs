@@ -0,0 +1,84 @@
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const EVENT_BUFFER_SIZE: usize = 1024;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn events<E>() -> (mpsc::Sender<E>, Events<E>) {
+    let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+    (
+        sender,
+        Events {
+            receiver,
+            timeout: DEFAULT_TIMEOUT,
+        },
+    )
+}
+
+#[derive(Debug)]
+pub struct Events<E> {
+    receiver: mpsc::Receiver<E>,
+    timeout: Duration,
+}
+
+impl<E: fmt::Debug> Events<E> {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn next(&mut self) -> Option<E> {
+        timeout(self.timeout, self.receiver.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn take(&mut self, n: usize) -> Vec<E> {
+        let mut events = Vec::with_capacity(n);
+        while events.len() < n {
+            match self.next().await {
+                Some(event) => events.push(event),
+                None => panic!("expected {n} events, got {}: {events:#?}", events.len()),
+            }
+        }
+        events
+    }
+
+    pub fn drain(&mut self) -> Vec<E> {
+        let mut events = Vec::new();
+        while let Ok(event) = self.receiver.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    pub async fn wait_for(&mut self, mut predicate: impl FnMut(&E) -> bool) -> E {
+        let mut skipped = Vec::new();
+        loop {
+            match self.next().await {
+                Some(event) if predicate(&event) => return event,
+                Some(event) => skipped.push(event),
+                None => panic!("no matching event, got: {skipped:#?}"),
+            }
+        }
+    }
+
+    pub async fn assert_none(&mut self, predicate: impl Fn(&E) -> bool) {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        while let Ok(Some(event)) = tokio::time::timeout_at(deadline, self.receiver.recv()).await {
+            assert!(!predicate(&event), "unexpected event: {event:#?}");
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! assert_event {
+    ($events:expr, $pattern:pat $(if $guard:expr)? $(,)?) => {
+        $events
+            .wait_for(|event| matches!(event, $pattern $(if $guard)?))
+            .await
+    };
+}
@@ -0,0 +1,342 @@
+use crate::address::Address;
+use crate::transport::{self, BoxFuture, Connection, Connector};
+use crate::{tcp, udp};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::sync::{self, mpsc};
+
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+type Datagram = (Vec<u8>, Address);
+
+#[derive(Debug)]
+struct Inner {
+    listeners: HashMap<Address, mpsc::UnboundedSender<MemoryStream>>,
+    sockets: HashMap<Address, mpsc::UnboundedSender<Datagram>>,
+    next_port: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                listeners: HashMap::new(),
+                sockets: HashMap::new(),
+                next_port: FIRST_EPHEMERAL_PORT,
+            })),
+        }
+    }
+
+    pub fn listen(&self, local_addr: impl Into<Address>) -> io::Result<MemoryListener> {
+        let local_addr = local_addr.into();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.listeners.contains_key(&local_addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{local_addr} is already listening"),
+            ));
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        inner.listeners.insert(local_addr.clone(), sender);
+        Ok(MemoryListener {
+            local_addr,
+            receiver: sync::Mutex::new(receiver),
+            network: self.clone(),
+        })
+    }
+
+    pub fn bind(&self, local_addr: impl Into<Address>) -> io::Result<MemoryDatagramSocket> {
+        let local_addr = local_addr.into();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.sockets.contains_key(&local_addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{local_addr} is already bound"),
+            ));
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        inner.sockets.insert(local_addr.clone(), sender);
+        Ok(MemoryDatagramSocket {
+            local_addr,
+            receiver: sync::Mutex::new(receiver),
+            network: self.clone(),
+        })
+    }
+
+    pub async fn connect(&self, remote_addr: impl Into<Address>) -> io::Result<MemoryStream> {
+        let remote_addr = remote_addr.into();
+        let local_addr = self.ephemeral_addr();
+        let sender = self
+            .inner
+            .lock()
+            .unwrap()
+            .listeners
+            .get(&remote_addr)
+            .cloned();
+        let (client, server) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+        let accepted = MemoryStream {
+            stream: server,
+            local_addr: remote_addr.clone(),
+            peer_addr: local_addr.clone(),
+        };
+        match sender.map(|sender| sender.send(accepted)) {
+            Some(Ok(())) => Ok(MemoryStream {
+                stream: client,
+                local_addr,
+                peer_addr: remote_addr,
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("nothing is listening on {remote_addr}"),
+            )),
+        }
+    }
+
+    pub fn connector(&self) -> MemoryConnector {
+        MemoryConnector {
+            network: self.clone(),
+        }
+    }
+
+    pub fn tcp_proxy(
+        &self,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> io::Result<tcp::ProxyBuilder> {
+        Ok(tcp::Proxy::builder()
+            .listener(self.listen(local_addr)?)
+            .connector(self.connector())
+            .remote_address(remote_addr))
+    }
+
+    pub fn udp_proxy(
+        &self,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+    ) -> io::Result<udp::ProxyBuilder> {
+        Ok(udp::Proxy::builder()
+            .socket(self.bind(local_addr)?)
+            .remote_address(remote_addr))
+    }
+
+    fn ephemeral_addr(&self) -> Address {
+        let mut inner = self.inner.lock().unwrap();
+        let port = inner.next_port;
+        inner.next_port = inner
+            .next_port
+            .checked_add(1)
+            .unwrap_or(FIRST_EPHEMERAL_PORT);
+        Address::Inet(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+    }
+
+    fn deliver(&self, datagram: &[u8], from_addr: &Address, to_addr: &Address) {
+        let sender = self.inner.lock().unwrap().sockets.get(to_addr).cloned();
+        if let Some(sender) = sender {
+            let _ = sender.send((datagram.to_vec(), from_addr.clone()));
+        }
+    }
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryListener {
+    local_addr: Address,
+    receiver: sync::Mutex<mpsc::UnboundedReceiver<MemoryStream>>,
+    network: MemoryNetwork,
+}
+
+impl MemoryListener {
+    pub async fn accept(&self) -> io::Result<(MemoryStream, Address)> {
+        match self.receiver.lock().await.recv().await {
+            Some(stream) => {
+                let peer_addr = stream.peer_addr.clone();
+                Ok((stream, peer_addr))
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "memory network closed",
+            )),
+        }
+    }
+
+    pub fn local_addr(&self) -> &Address {
+        &self.local_addr
+    }
+}
+
+impl transport::Listener for MemoryListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(Box<dyn Connection>, Address)>> {
+        Box::pin(async move {
+            let (stream, peer_addr) = MemoryListener::accept(self).await?;
+            Ok((Box::new(stream) as Box<dyn Connection>, peer_addr))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        Ok(self.local_addr.clone())
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.network.inner.lock() {
+            inner.listeners.remove(&self.local_addr);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryConnector {
+    network: MemoryNetwork,
+}
+
+impl Connector for MemoryConnector {
+    fn connect<'a>(
+        &'a self,
+        remote_addr: &'a Address,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Connection>>> {
+        Box::pin(async move {
+            let stream = self.network.connect(remote_addr.clone()).await?;
+            Ok(Box::new(stream) as Box<dyn Connection>)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryStream {
+    stream: DuplexStream,
+    local_addr: Address,
+    peer_addr: Address,
+}
+
+impl Connection for MemoryStream {
+    fn local_addr(&self) -> io::Result<Address> {
+        Ok(self.local_addr.clone())
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        Ok(self.peer_addr.clone())
+    }
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryDatagramSocket {
+    local_addr: Address,
+    receiver: sync::Mutex<mpsc::UnboundedReceiver<Datagram>>,
+    network: MemoryNetwork,
+}
+
+impl MemoryDatagramSocket {
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Address)> {
+        match self.receiver.lock().await.recv().await {
+            Some((datagram, from_addr)) => {
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Ok((len, from_addr))
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "memory network closed",
+            )),
+        }
+    }
+
+    pub fn send_to(&self, buf: &[u8], target: impl Into<Address>) -> io::Result<usize> {
+        self.network.deliver(buf, &self.local_addr, &target.into());
+        Ok(buf.len())
+    }
+
+    pub fn local_addr(&self) -> &Address {
+        &self.local_addr
+    }
+}
+
+impl transport::DatagramSocket for MemoryDatagramSocket {
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, Address)>> {
+        Box::pin(MemoryDatagramSocket::recv_from(self, buf))
+    }
+
+    fn send_to<'a>(
+        &'a self,
+        buf: &'a [u8],
+        target: &'a Address,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move { MemoryDatagramSocket::send_to(self, buf, target.clone()) })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        Ok(self.local_addr.clone())
+    }
+}
+
+impl Drop for MemoryDatagramSocket {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.network.inner.lock() {
+            inner.sockets.remove(&self.local_addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::tests::assert_round_trip;
+
+    #[tokio::test]
+    async fn memory_adapter_reports_addresses_and_round_trips() {
+        let net = MemoryNetwork::new();
+        let local_addr = Address::from(SocketAddr::from(([10, 0, 0, 1], 80)));
+        let listener = net.listen(local_addr.clone()).unwrap();
+        assert_eq!(
+            transport::Listener::local_addr(&listener).unwrap(),
+            local_addr
+        );
+
+        let client = net.connector().connect(&local_addr).await.unwrap();
+        assert_eq!(client.peer_addr().unwrap(), local_addr);
+        assert_round_trip(&listener, client).await;
+    }
+}
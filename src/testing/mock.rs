@@ -0,0 +1,107 @@
+use super::MemoryNetwork;
+use crate::address::Address;
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Expect(Vec<u8>),
+    Send(Vec<u8>),
+    Delay(Duration),
+    Close,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(mut self, data: impl AsRef<[u8]>) -> Self {
+        self.steps.push(Step::Expect(data.as_ref().to_vec()));
+        self
+    }
+
+    pub fn send(mut self, data: impl AsRef<[u8]>) -> Self {
+        self.steps.push(Step::Send(data.as_ref().to_vec()));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(Step::Delay(delay));
+        self
+    }
+
+    pub fn close(mut self) -> Self {
+        self.steps.push(Step::Close);
+        self
+    }
+
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> io::Result<()> {
+        for step in &self.steps {
+            match step {
+                Step::Expect(expected) => {
+                    let mut actual = vec![0; expected.len()];
+                    stream.read_exact(&mut actual).await?;
+                    if &actual != expected {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "expected {:?}, got {:?}",
+                                String::from_utf8_lossy(expected),
+                                String::from_utf8_lossy(&actual)
+                            ),
+                        ));
+                    }
+                }
+                Step::Send(data) => stream.write_all(data).await?,
+                Step::Delay(delay) => sleep(*delay).await,
+                Step::Close => break,
+            }
+        }
+        stream.shutdown().await
+    }
+}
+
+impl MemoryNetwork {
+    pub fn echo(&self, local_addr: impl Into<Address>) -> io::Result<JoinHandle<()>> {
+        let listener = self.listen(local_addr)?;
+        Ok(tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = io::split(&mut stream);
+                    let _ = io::copy(&mut reader, &mut writer).await;
+                    let _ = writer.shutdown().await;
+                });
+            }
+        }))
+    }
+
+    pub fn scripted(
+        &self,
+        local_addr: impl Into<Address>,
+        script: Script,
+    ) -> io::Result<JoinHandle<io::Result<()>>> {
+        let listener = self.listen(local_addr)?;
+        Ok(tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            script.run(&mut stream).await
+        }))
+    }
+
+    pub fn udp_echo(&self, local_addr: impl Into<Address>) -> io::Result<JoinHandle<()>> {
+        let socket = self.bind(local_addr)?;
+        Ok(tokio::spawn(async move {
+            let mut buf = vec![0; u16::MAX as usize];
+            while let Ok((len, from_addr)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&buf[..len], from_addr);
+            }
+        }))
+    }
+}
@@ -0,0 +1,9 @@
+mod events;
+mod memory;
+mod mock;
+
+pub use self::events::{events, Events};
+pub use self::memory::{
+    MemoryConnector, MemoryDatagramSocket, MemoryListener, MemoryNetwork, MemoryStream,
+};
+pub use self::mock::Script;
@@ -0,0 +1,450 @@
+use crate::address::Address;
+use crate::net::Socket;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_rustls::client;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+#[derive(Debug, Clone)]
+enum Pem {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+impl Pem {
+    fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Pem::Bytes(bytes) => Ok(bytes.clone()),
+            Pem::File(path) => fs::read(path),
+        }
+    }
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_owned())
+}
+
+pub(crate) fn load_certificates(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = rustls_pemfile::certs(&mut &*pem).collect::<io::Result<Vec<_>>>()?;
+    if certificates.is_empty() {
+        return Err(invalid_input("no certificate found in PEM"));
+    }
+    Ok(certificates)
+}
+
+pub(crate) fn load_private_key(pem: &[u8]) -> io::Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut &*pem)?
+        .ok_or_else(|| invalid_input("no private key found in PEM"))
+}
+
+fn load_roots(pems: &[Pem]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for pem in pems {
+        for certificate in load_certificates(&pem.read()?)? {
+            roots
+                .add(certificate)
+                .map_err(|e| invalid_input(&e.to_string()))?;
+        }
+    }
+    Ok(roots)
+}
+
+#[derive(Debug, Clone)]
+struct Identity {
+    certificates: Pem,
+    private_key: Pem,
+}
+
+impl Identity {
+    fn load(&self) -> io::Result<Arc<CertifiedKey>> {
+        let certificates = load_certificates(&self.certificates.read()?)?;
+        let private_key = load_private_key(&self.private_key.read()?)?;
+        let signing_key =
+            any_supported_type(&private_key).map_err(|e| invalid_input(&e.to_string()))?;
+        Ok(Arc::new(CertifiedKey::new(certificates, signing_key)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsServerConfig {
+    default: Option<Identity>,
+    server_names: Vec<(String, Identity)>,
+    client_cas: Vec<Pem>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn certificate_pem(
+        mut self,
+        certificates: impl Into<Vec<u8>>,
+        private_key: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.default = Some(Identity {
+            certificates: Pem::Bytes(certificates.into()),
+            private_key: Pem::Bytes(private_key.into()),
+        });
+        self
+    }
+
+    pub fn certificate_pem_files(
+        mut self,
+        certificates: impl Into<PathBuf>,
+        private_key: impl Into<PathBuf>,
+    ) -> Self {
+        self.default = Some(Identity {
+            certificates: Pem::File(certificates.into()),
+            private_key: Pem::File(private_key.into()),
+        });
+        self
+    }
+
+    pub fn sni_certificate_pem(
+        mut self,
+        server_name: impl Into<String>,
+        certificates: impl Into<Vec<u8>>,
+        private_key: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.server_names.push((
+            server_name.into(),
+            Identity {
+                certificates: Pem::Bytes(certificates.into()),
+                private_key: Pem::Bytes(private_key.into()),
+            },
+        ));
+        self
+    }
+
+    pub fn sni_certificate_pem_files(
+        mut self,
+        server_name: impl Into<String>,
+        certificates: impl Into<PathBuf>,
+        private_key: impl Into<PathBuf>,
+    ) -> Self {
+        self.server_names.push((
+            server_name.into(),
+            Identity {
+                certificates: Pem::File(certificates.into()),
+                private_key: Pem::File(private_key.into()),
+            },
+        ));
+        self
+    }
+
+    pub fn client_ca_pem(mut self, certificates: impl Into<Vec<u8>>) -> Self {
+        self.client_cas.push(Pem::Bytes(certificates.into()));
+        self
+    }
+
+    pub fn client_ca_pem_file(mut self, certificates: impl Into<PathBuf>) -> Self {
+        self.client_cas.push(Pem::File(certificates.into()));
+        self
+    }
+
+    pub fn alpn_protocol(mut self, protocol: impl Into<Vec<u8>>) -> Self {
+        self.alpn_protocols.push(protocol.into());
+        self
+    }
+
+    pub(crate) fn build(self) -> io::Result<rustls::ServerConfig> {
+        if self.default.is_none() && self.server_names.is_empty() {
+            return Err(invalid_input(
+                "TLS listener requires at least one certificate",
+            ));
+        }
+        let resolver = CertificateResolver {
+            default: self.default.as_ref().map(Identity::load).transpose()?,
+            server_names: self
+                .server_names
+                .iter()
+                .map(|(name, identity)| Ok((name.to_ascii_lowercase(), identity.load()?)))
+                .collect::<io::Result<_>>()?,
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid_input(&e.to_string()))?;
+        let builder = if self.client_cas.is_empty() {
+            builder.with_no_client_auth()
+        } else {
+            let roots = load_roots(&self.client_cas)?;
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| invalid_input(&e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        };
+        let mut config = builder.with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = self.alpn_protocols;
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+struct CertificateResolver {
+    default: Option<Arc<CertifiedKey>>,
+    server_names: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.server_names.get(&name.to_ascii_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsClientConfig {
+    server_name: Option<String>,
+    ca_certificates: Vec<Pem>,
+    client_identity: Option<Identity>,
+    insecure: bool,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    pub fn ca_certificate_pem(mut self, certificates: impl Into<Vec<u8>>) -> Self {
+        self.ca_certificates.push(Pem::Bytes(certificates.into()));
+        self
+    }
+
+    pub fn ca_certificate_pem_file(mut self, certificates: impl Into<PathBuf>) -> Self {
+        self.ca_certificates.push(Pem::File(certificates.into()));
+        self
+    }
+
+    pub fn client_certificate_pem(
+        mut self,
+        certificates: impl Into<Vec<u8>>,
+        private_key: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.client_identity = Some(Identity {
+            certificates: Pem::Bytes(certificates.into()),
+            private_key: Pem::Bytes(private_key.into()),
+        });
+        self
+    }
+
+    pub fn client_certificate_pem_files(
+        mut self,
+        certificates: impl Into<PathBuf>,
+        private_key: impl Into<PathBuf>,
+    ) -> Self {
+        self.client_identity = Some(Identity {
+            certificates: Pem::File(certificates.into()),
+            private_key: Pem::File(private_key.into()),
+        });
+        self
+    }
+
+    pub fn insecure(mut self) -> Self {
+        self.insecure = true;
+        self
+    }
+
+    pub fn alpn_protocol(mut self, protocol: impl Into<Vec<u8>>) -> Self {
+        self.alpn_protocols.push(protocol.into());
+        self
+    }
+
+    pub(crate) fn build(self) -> io::Result<TlsConnector> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid_input(&e.to_string()))?;
+        let builder = if self.insecure {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(InsecureVerifier { provider }))
+        } else {
+            let roots = if self.ca_certificates.is_empty() {
+                RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                }
+            } else {
+                load_roots(&self.ca_certificates)?
+            };
+            builder.with_root_certificates(roots)
+        };
+        let mut config = match &self.client_identity {
+            Some(identity) => {
+                let certificates = load_certificates(&identity.certificates.read()?)?;
+                let private_key = load_private_key(&identity.private_key.read()?)?;
+                builder
+                    .with_client_auth_cert(certificates, private_key)
+                    .map_err(|e| invalid_input(&e.to_string()))?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = self.alpn_protocols;
+        let server_name = self
+            .server_name
+            .map(ServerName::try_from)
+            .transpose()
+            .map_err(|e| invalid_input(&e.to_string()))?;
+        Ok(TlsConnector {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TlsConnector {
+    config: Arc<rustls::ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl TlsConnector {
+    pub(crate) async fn connect(
+        &self,
+        stream: Socket,
+        remote_addr: &Address,
+    ) -> io::Result<client::TlsStream<Socket>> {
+        let server_name = match (&self.server_name, remote_addr.ip()) {
+            (Some(server_name), _) => server_name.clone(),
+            (None, Some(ip)) => ServerName::IpAddress(ip.into()),
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "upstream TLS to a unix socket requires a server name",
+                ))
+            }
+        };
+        tokio_rustls::TlsConnector::from(self.config.clone())
+            .connect(server_name, stream)
+            .await
+    }
+}
+
+#[derive(Debug)]
+struct InsecureVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn ip_addr(octets: &[u8]) -> Option<IpAddr> {
+    match octets.len() {
+        4 => <[u8; 4]>::try_from(octets).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(octets).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub subject: String,
+    pub subject_alt_names: Vec<String>,
+    pub fingerprint: String,
+}
+
+impl ClientIdentity {
+    pub(crate) fn from_der(certificate: &[u8]) -> Option<Self> {
+        let (_, parsed) = X509Certificate::from_der(certificate).ok()?;
+        let subject_alt_names = parsed
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|extension| {
+                extension
+                    .value
+                    .general_names
+                    .iter()
+                    .map(|name| match name {
+                        GeneralName::DNSName(name) => format!("DNS:{name}"),
+                        GeneralName::RFC822Name(name) => format!("email:{name}"),
+                        GeneralName::URI(uri) => format!("URI:{uri}"),
+                        GeneralName::IPAddress(ip) => match ip_addr(ip) {
+                            Some(ip) => format!("IP:{ip}"),
+                            None => name.to_string(),
+                        },
+                        name => name.to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut fingerprint = String::with_capacity(64);
+        for byte in Sha256::digest(certificate) {
+            let _ = write!(fingerprint, "{byte:02x}");
+        }
+        Some(Self {
+            subject: parsed.subject().to_string(),
+            subject_alt_names,
+            fingerprint,
+        })
+    }
+}
@@ -0,0 +1,224 @@
+use crate::address::Address;
+#[cfg(unix)]
+use crate::address::UnixAddress;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[cfg(feature = "turmoil")]
+mod turmoil;
+
+#[cfg(feature = "turmoil")]
+pub use self::turmoil::{TurmoilConnector, TurmoilListener};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + Sync + fmt::Debug {
+    fn local_addr(&self) -> io::Result<Address>;
+
+    fn peer_addr(&self) -> io::Result<Address>;
+}
+
+pub trait Listener: Send + Sync + fmt::Debug {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(Box<dyn Connection>, Address)>>;
+
+    fn local_addr(&self) -> io::Result<Address>;
+}
+
+pub trait Connector: Send + Sync + fmt::Debug {
+    fn connect<'a>(
+        &'a self,
+        remote_addr: &'a Address,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Connection>>>;
+
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok(lookup_host((host, port)).await?.collect()) })
+    }
+}
+
+pub trait DatagramSocket: Send + Sync + fmt::Debug {
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, Address)>>;
+
+    fn send_to<'a>(
+        &'a self,
+        buf: &'a [u8],
+        target: &'a Address,
+    ) -> BoxFuture<'a, io::Result<usize>>;
+
+    fn local_addr(&self) -> io::Result<Address>;
+}
+
+// tokio's unix `SocketAddr` hides abstract names, so addresses are read
+// through socket2 instead.
+#[cfg(unix)]
+fn unix_address(addr: socket2::SockAddr) -> Address {
+    Address::Unix(UnixAddress::from_socket2(&addr))
+}
+
+fn inet_target(target: &Address) -> io::Result<SocketAddr> {
+    target.inet().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "datagram target does not match the socket family",
+        )
+    })
+}
+
+impl Connection for TcpStream {
+    fn local_addr(&self) -> io::Result<Address> {
+        TcpStream::local_addr(self).map(Address::Inet)
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        TcpStream::peer_addr(self).map(Address::Inet)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn local_addr(&self) -> io::Result<Address> {
+        socket2::SockRef::from(self).local_addr().map(unix_address)
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        socket2::SockRef::from(self).peer_addr().map(unix_address)
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(Box<dyn Connection>, Address)>> {
+        Box::pin(async move {
+            let (stream, peer_addr) = TcpListener::accept(self).await?;
+            Ok((
+                Box::new(stream) as Box<dyn Connection>,
+                Address::Inet(peer_addr),
+            ))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        TcpListener::local_addr(self).map(Address::Inet)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(Box<dyn Connection>, Address)>> {
+        Box::pin(async move {
+            let (stream, _) = UnixListener::accept(self).await?;
+            let peer_addr = Connection::peer_addr(&stream)?;
+            Ok((Box::new(stream) as Box<dyn Connection>, peer_addr))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        socket2::SockRef::from(self).local_addr().map(unix_address)
+    }
+}
+
+impl DatagramSocket for UdpSocket {
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, Address)>> {
+        Box::pin(async move {
+            let (len, addr) = UdpSocket::recv_from(self, buf).await?;
+            Ok((len, Address::Inet(addr)))
+        })
+    }
+
+    fn send_to<'a>(
+        &'a self,
+        buf: &'a [u8],
+        target: &'a Address,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move { UdpSocket::send_to(self, buf, inet_target(target)?).await })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        UdpSocket::local_addr(self).map(Address::Inet)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    pub(crate) async fn assert_round_trip(
+        listener: &dyn Listener,
+        mut client: Box<dyn Connection>,
+    ) {
+        let (mut server, peer_addr) = listener.accept().await.unwrap();
+        assert_eq!(peer_addr, client.local_addr().unwrap());
+        assert_eq!(server.peer_addr().unwrap(), peer_addr);
+        assert_eq!(server.local_addr().unwrap(), listener.local_addr().unwrap());
+        assert_eq!(client.peer_addr().unwrap(), listener.local_addr().unwrap());
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn tcp_adapter_reports_addresses_and_round_trips() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = Listener::local_addr(&listener).unwrap();
+        assert_eq!(local_addr, Address::Inet(listener.local_addr().unwrap()));
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_round_trip(&listener, Box::new(client)).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_adapter_reports_addresses_and_round_trips() {
+        let path =
+            std::env::temp_dir().join(format!("proxy-rs-{}-transport.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        assert_eq!(
+            Listener::local_addr(&listener).unwrap(),
+            Address::Unix(UnixAddress::pathname(&path))
+        );
+        let client = UnixStream::connect(&path).await.unwrap();
+        assert_eq!(
+            Connection::local_addr(&client).unwrap(),
+            Address::Unix(UnixAddress::Unnamed)
+        );
+        assert_round_trip(&listener, Box::new(client)).await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn unix_adapter_keeps_abstract_names() {
+        let name = format!("proxy-rs-{}-transport", std::process::id());
+        let local_addr = UnixAddress::abstract_name(&name);
+        let listener =
+            std::os::unix::net::UnixListener::bind_addr(&local_addr.to_std().unwrap()).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = UnixListener::from_std(listener).unwrap();
+        assert_eq!(
+            Listener::local_addr(&listener).unwrap(),
+            Address::Unix(local_addr.clone())
+        );
+
+        let client =
+            std::os::unix::net::UnixStream::connect_addr(&local_addr.to_std().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let client = UnixStream::from_std(client).unwrap();
+        assert_round_trip(&listener, Box::new(client)).await;
+    }
+}
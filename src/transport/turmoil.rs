@@ -0,0 +1,104 @@
+use super::{inet_target, BoxFuture, Connection, Connector, DatagramSocket, Listener};
+use crate::address::Address;
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::io;
+use turmoil::net::{TcpListener, TcpStream, UdpSocket};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TurmoilConnector;
+
+impl Connector for TurmoilConnector {
+    fn connect<'a>(
+        &'a self,
+        remote_addr: &'a Address,
+    ) -> BoxFuture<'a, io::Result<Box<dyn Connection>>> {
+        Box::pin(async move {
+            let remote_addr = remote_addr.inet().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "simulated networks only carry inet addresses",
+                )
+            })?;
+            let stream = TcpStream::connect(remote_addr).await?;
+            Ok(Box::new(stream) as Box<dyn Connection>)
+        })
+    }
+
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok(turmoil::net::lookup_host((host, port)).await?.collect()) })
+    }
+}
+
+impl Connection for TcpStream {
+    fn local_addr(&self) -> io::Result<Address> {
+        TcpStream::local_addr(self).map(Address::Inet)
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        TcpStream::peer_addr(self).map(Address::Inet)
+    }
+}
+
+pub struct TurmoilListener(TcpListener);
+
+impl TurmoilListener {
+    pub async fn bind(local_addr: SocketAddr) -> io::Result<Self> {
+        TcpListener::bind(local_addr).await.map(TurmoilListener)
+    }
+}
+
+impl From<TcpListener> for TurmoilListener {
+    fn from(value: TcpListener) -> Self {
+        TurmoilListener(value)
+    }
+}
+
+impl fmt::Debug for TurmoilListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TurmoilListener")
+            .field(&self.0.local_addr().ok())
+            .finish()
+    }
+}
+
+impl Listener for TurmoilListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(Box<dyn Connection>, Address)>> {
+        Box::pin(async move {
+            let (stream, peer_addr) = self.0.accept().await?;
+            Ok((
+                Box::new(stream) as Box<dyn Connection>,
+                Address::Inet(peer_addr),
+            ))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        self.0.local_addr().map(Address::Inet)
+    }
+}
+
+impl DatagramSocket for UdpSocket {
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<(usize, Address)>> {
+        Box::pin(async move {
+            let (len, addr) = UdpSocket::recv_from(self, buf).await?;
+            Ok((len, Address::Inet(addr)))
+        })
+    }
+
+    fn send_to<'a>(
+        &'a self,
+        buf: &'a [u8],
+        target: &'a Address,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(async move { UdpSocket::send_to(self, buf, inet_target(target)?).await })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        UdpSocket::local_addr(self).map(Address::Inet)
+    }
+}
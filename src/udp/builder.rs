@@ -1,8 +1,19 @@
-use super::{Event, Proxy};
-use std::collections::VecDeque;
+use super::{Event, Proxy, Relay};
+use crate::acl::{AccessList, Cidr};
+use crate::address::{Address, UnixAddress};
+use crate::capture::Capture;
+use crate::error::Error;
+use crate::fault::FaultInjector;
+use crate::limit::{Buckets, KeyedBuckets, Limits, RateLimit};
+use crate::net::DatagramSocket;
+use crate::proxy_protocol::{ProxyProtocol, Version};
+use crate::record::Recorder;
+use crate::transport;
+use crate::Direction;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use tokio::io;
-use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
+use tokio::net::{lookup_host, ToSocketAddrs};
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
 
@@ -12,6 +23,18 @@ pub struct ProxyBuilder {
     remote_addrs_handles: JoinSet<io::Result<Vec<SocketAddr>>>,
     event_sender: Option<mpsc::Sender<Event>>,
     buffer_size: usize,
+    access_list: AccessList,
+    client_limits: Limits,
+    client_packet_limits: Limits,
+    global_limits: Limits,
+    global_packet_limits: Limits,
+    faults: FaultInjector,
+    recorder: Option<Recorder>,
+    capture: Option<Capture>,
+    proxy_protocol: Option<ProxyProtocol>,
+    local_unix_addr: Option<UnixAddress>,
+    remote_addresses: Vec<Address>,
+    socket: Option<Box<dyn transport::DatagramSocket>>,
 }
 
 impl ProxyBuilder {
@@ -21,6 +44,18 @@ impl ProxyBuilder {
             remote_addrs_handles: JoinSet::new(),
             event_sender: None,
             buffer_size: 1024,
+            access_list: AccessList::new(),
+            client_limits: Limits::default(),
+            client_packet_limits: Limits::default(),
+            global_limits: Limits::default(),
+            global_packet_limits: Limits::default(),
+            faults: FaultInjector::default(),
+            recorder: None,
+            capture: None,
+            proxy_protocol: None,
+            local_unix_addr: None,
+            remote_addresses: Vec::new(),
+            socket: None,
         }
     }
     pub fn local_addrs<A: ToSocketAddrs + Send + 'static>(mut self, local_addrs: A) -> Self {
@@ -41,6 +76,28 @@ impl ProxyBuilder {
         self
     }
 
+    #[cfg(unix)]
+    pub fn local_unix_addr(mut self, local_addr: UnixAddress) -> Self {
+        self.local_unix_addr = Some(local_addr);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn remote_unix_addr(mut self, remote_addr: UnixAddress) -> Self {
+        self.remote_addresses.push(Address::Unix(remote_addr));
+        self
+    }
+
+    pub fn remote_address(mut self, remote_addr: impl Into<Address>) -> Self {
+        self.remote_addresses.push(remote_addr.into());
+        self
+    }
+
+    pub fn socket(mut self, socket: impl transport::DatagramSocket + 'static) -> Self {
+        self.socket = Some(Box::new(socket));
+        self
+    }
+
     pub fn event_sender(mut self, event_sender: mpsc::Sender<Event>) -> Self {
         self.event_sender = Some(event_sender);
         self
@@ -51,26 +108,157 @@ impl ProxyBuilder {
         self
     }
 
-    pub async fn build(mut self) -> io::Result<Proxy> {
-        let mut local_addrs = Vec::new();
-        while let Some(l) = self.local_addrs_handles.join_next().await {
-            local_addrs.extend(l.unwrap()?);
+    pub fn allow(self, cidr: Cidr) -> Self {
+        self.access_list.allow(cidr);
+        self
+    }
+
+    pub fn deny(self, cidr: Cidr) -> Self {
+        self.access_list.deny(cidr);
+        self
+    }
+
+    pub fn access_list(mut self, access_list: AccessList) -> Self {
+        self.access_list = access_list;
+        self
+    }
+
+    pub fn client_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.client_limits.set(direction, limit);
+        self
+    }
+
+    pub fn client_packet_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.client_packet_limits.set(direction, limit);
+        self
+    }
+
+    pub fn global_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.global_limits.set(direction, limit);
+        self
+    }
+
+    pub fn global_packet_rate_limit(mut self, direction: Direction, limit: RateLimit) -> Self {
+        self.global_packet_limits.set(direction, limit);
+        self
+    }
+
+    pub fn faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub fn recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn send_proxy_protocol(mut self, proxy_protocol: ProxyProtocol) -> Self {
+        self.proxy_protocol = Some(proxy_protocol);
+        self
+    }
+
+    pub async fn build(mut self) -> Result<Proxy, Error> {
+        let local_addrs: Vec<SocketAddr> = Error::resolve_all(&mut self.local_addrs_handles)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        let remote_inet_addrs: Vec<SocketAddr> = Error::resolve_all(&mut self.remote_addrs_handles)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        let is_unix_remote = self.remote_addresses.iter().any(Address::is_unix);
+        let remote_addrs: Vec<Address> = remote_inet_addrs
+            .into_iter()
+            .map(Address::Inet)
+            .chain(self.remote_addresses)
+            .collect();
+        let Some(remote_addr) = remote_addrs.first().cloned() else {
+            return Err(Error::NoRemoteAddresses);
+        };
+        if self
+            .proxy_protocol
+            .as_ref()
+            .is_some_and(|p| p.version() == Version::V1)
+        {
+            return Err(Error::InvalidConfig(
+                "PROXY protocol v1 does not support datagrams",
+            ));
+        }
+        if let Some(proxy_protocol) = &self.proxy_protocol {
+            proxy_protocol.validate()?;
         }
-        let mut remote_addrs = Vec::new();
-        while let Some(r) = self.remote_addrs_handles.join_next().await {
-            remote_addrs.extend(r.unwrap()?);
+        for limits in [
+            &self.client_limits,
+            &self.client_packet_limits,
+            &self.global_limits,
+            &self.global_packet_limits,
+        ] {
+            limits.validate()?;
         }
-        let local_socket = UdpSocket::bind(&*local_addrs).await?;
+        let local_socket =
+            match (self.socket, self.local_unix_addr) {
+                (None, None) if local_addrs.is_empty() => return Err(Error::NoLocalAddresses),
+                (None, None) if !is_unix_remote => DatagramSocket::bind_udp(&local_addrs)
+                    .await
+                    .map_err(Error::Bind)?,
+                #[cfg(unix)]
+                (None, Some(local_unix_addr))
+                    if local_addrs.is_empty() && remote_addrs.iter().all(Address::is_unix) =>
+                {
+                    DatagramSocket::bind_unix(&local_unix_addr).map_err(Error::Bind)?
+                }
+                (Some(socket), None) if local_addrs.is_empty() => DatagramSocket::Custom(socket),
+                _ => return Err(Error::InvalidConfig(
+                    "udp proxy binds one local socket of the same family as its remote addresses",
+                )),
+            };
+        let local_addr = local_socket.local_addr().map_err(Error::Bind)?;
         let event_sender = self.event_sender;
         let buffer_size = self.buffer_size;
+        let access_list = self.access_list;
+        let client_buckets = KeyedBuckets::new(self.client_limits);
+        let client_packet_buckets = KeyedBuckets::new(self.client_packet_limits);
+        let global_buckets = Buckets::new(self.global_limits);
+        let global_packet_buckets = Buckets::new(self.global_packet_limits);
+        let faults = self.faults;
+        let recorder = self.recorder;
+        let capture = self.capture;
+        let proxy_protocol = self.proxy_protocol;
         let queue = VecDeque::new();
+        let relay = Relay::new(local_socket, faults.clone());
 
         Ok(Proxy {
-            socket: local_socket,
+            relay,
+            local_addr,
+            remote_addr,
             remote_addrs,
             event_sender,
             buffer_size,
+            access_list,
+            client_buckets,
+            client_packet_buckets,
+            global_buckets,
+            global_packet_buckets,
+            faults,
+            recorder,
+            capture,
+            proxy_protocol,
+            recordings: HashMap::new(),
             queue,
         })
     }
 }
+
+impl Default for ProxyBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
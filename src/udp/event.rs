@@ -1,17 +1,32 @@
+use crate::address::Address;
 use std::io;
-use std::net::SocketAddr;
 
 #[derive(Debug)]
 pub enum Event {
+    Listening(Listening),
     Message(Message),
     MessageError(MessageError),
+    Dropped(Dropped),
+    Held(Held),
+    Rejected(Rejected),
+    RateLimited(RateLimited),
+}
+
+#[derive(Debug)]
+pub struct Listening {
+    pub local_addr: Address,
+}
+impl From<Listening> for Event {
+    fn from(event: Listening) -> Self {
+        Event::Listening(event)
+    }
 }
 
 #[derive(Debug)]
 pub struct Message {
-    pub from_addr: SocketAddr,
-    pub local_addr: SocketAddr,
-    pub to_addr: SocketAddr,
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
     pub message: String,
 }
 impl From<Message> for Event {
@@ -22,9 +37,9 @@ impl From<Message> for Event {
 
 #[derive(Debug)]
 pub struct MessageError {
-    pub from_addr: Option<SocketAddr>,
-    pub local_addr: SocketAddr,
-    pub to_addr: SocketAddr,
+    pub from_addr: Option<Address>,
+    pub local_addr: Address,
+    pub to_addr: Address,
     pub error: io::Error,
 }
 impl From<MessageError> for Event {
@@ -32,3 +47,52 @@ impl From<MessageError> for Event {
         Event::MessageError(event)
     }
 }
+
+#[derive(Debug)]
+pub struct Dropped {
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
+    pub message: String,
+}
+impl From<Dropped> for Event {
+    fn from(event: Dropped) -> Self {
+        Event::Dropped(event)
+    }
+}
+
+#[derive(Debug)]
+pub struct Held {
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
+    pub message: String,
+}
+impl From<Held> for Event {
+    fn from(event: Held) -> Self {
+        Event::Held(event)
+    }
+}
+
+#[derive(Debug)]
+pub struct Rejected {
+    pub client_addr: Address,
+    pub local_addr: Address,
+}
+impl From<Rejected> for Event {
+    fn from(event: Rejected) -> Self {
+        Event::Rejected(event)
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimited {
+    pub from_addr: Address,
+    pub local_addr: Address,
+    pub to_addr: Address,
+}
+impl From<RateLimited> for Event {
+    fn from(event: RateLimited) -> Self {
+        Event::RateLimited(event)
+    }
+}
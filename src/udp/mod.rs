@@ -1,15 +1,40 @@
 mod builder;
 mod event;
+mod relay;
+mod replay;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::net::{IpAddr, SocketAddr};
-use tokio::net::UdpSocket;
+use std::time::Duration;
+use tokio::io;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::acl::AccessList;
+use crate::address::Address;
+use crate::capture::Capture;
+use crate::fault::FaultInjector;
+use crate::limit::{self, Buckets, KeyedBuckets};
+use crate::proxy_protocol::{ProxyProtocol, Transport};
+use crate::record::{Protocol, Recorder};
+use crate::Direction;
+
+pub(crate) use self::relay::{Delivery, Relay};
 
 pub use self::builder::ProxyBuilder;
 pub use self::event::Event;
+pub use self::replay::Replay;
+
+const RECORDING_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn are_addrs_eq(addr1: &Address, addr2: &Address) -> bool {
+    match (addr1, addr2) {
+        (Address::Inet(addr1), Address::Inet(addr2)) => are_inet_addrs_eq(addr1, addr2),
+        _ => addr1 == addr2,
+    }
+}
 
-fn are_addrs_eq(addr1: &SocketAddr, addr2: &SocketAddr) -> bool {
+fn are_inet_addrs_eq(addr1: &SocketAddr, addr2: &SocketAddr) -> bool {
     let ip1 = match addr1.ip() {
         IpAddr::V4(ip) => ip.to_ipv6_mapped(),
         IpAddr::V6(ip) => ip,
@@ -24,12 +49,24 @@ fn are_addrs_eq(addr1: &SocketAddr, addr2: &SocketAddr) -> bool {
 #[derive(Debug)]
 pub struct Proxy {
     buffer_size: usize,
-    remote_addrs: Vec<SocketAddr>,
+    remote_addrs: Vec<Address>,
+    access_list: AccessList,
+    client_buckets: KeyedBuckets<IpAddr>,
+    client_packet_buckets: KeyedBuckets<IpAddr>,
+    global_buckets: Buckets,
+    global_packet_buckets: Buckets,
+    faults: FaultInjector,
+    recorder: Option<Recorder>,
+    recordings: HashMap<Address, (u64, Instant)>,
+    capture: Option<Capture>,
+    proxy_protocol: Option<ProxyProtocol>,
 
-    socket: UdpSocket,
+    relay: Relay,
+    local_addr: Address,
+    remote_addr: Address,
     event_sender: Option<mpsc::Sender<Event>>,
 
-    queue: VecDeque<(String, SocketAddr)>,
+    queue: VecDeque<(String, Address)>,
 }
 
 impl Proxy {
@@ -37,60 +74,163 @@ impl Proxy {
         ProxyBuilder::new()
     }
 
-    pub async fn run(mut self) {
-        while let Some((msg, client_addr)) = self.get_message().await {
-            self.send_message_to_remote(&msg, &client_addr).await;
-            self.send_event(Event::from(event::Message {
-                from_addr: client_addr,
-                local_addr: self.local_addr(),
-                to_addr: self.remote_addr(),
-                message: msg,
-            }))
-            .await;
+    pub fn access_list(&self) -> AccessList {
+        self.access_list.clone()
+    }
+
+    pub fn faults(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+
+    pub fn local_addr(&self) -> Address {
+        self.local_addr.clone()
+    }
+
+    pub async fn run(mut self) -> io::Result<()> {
+        self.send_event(Event::from(event::Listening {
+            local_addr: self.local_addr(),
+        }))
+        .await;
+        loop {
+            let (msg, client_addr) = self.get_message().await?;
+            if client_addr
+                .ip()
+                .is_some_and(|ip| !self.access_list.is_allowed(ip))
+            {
+                self.send_event(Event::from(event::Rejected {
+                    client_addr: client_addr.clone(),
+                    local_addr: self.local_addr(),
+                }))
+                .await;
+                continue;
+            }
+            if !self.admit(client_addr.ip(), Direction::Upload, msg.len()) {
+                self.send_event(Event::from(event::RateLimited {
+                    from_addr: client_addr.clone(),
+                    local_addr: self.local_addr(),
+                    to_addr: self.remote_addr(),
+                }))
+                .await;
+                continue;
+            }
+            let delivery = self.send_message_to_remote(&msg, &client_addr).await;
+            self.record(&client_addr, Direction::Upload, msg.as_bytes());
+            if let (Some(capture), Some((client, local, remote))) =
+                (&self.capture, self.inet_addrs(&client_addr))
+            {
+                capture.udp(client, local, msg.as_bytes());
+                if matches!(delivery, Some(Delivery::Sent | Delivery::Held)) {
+                    capture.udp(local, remote, msg.as_bytes());
+                }
+            }
+            let remote_addr = self.remote_addr();
+            if !self
+                .report_delivery(delivery, client_addr.clone(), remote_addr, msg)
+                .await
+            {
+                continue;
+            }
 
             let Some(reply) = self.recv_reply().await else {
                 continue;
             };
-            self.send_message_to_client(&reply, &client_addr).await;
-            self.send_event(Event::from(event::Message {
-                from_addr: self.remote_addr(),
-                local_addr: self.local_addr(),
-                to_addr: client_addr,
-                message: reply,
-            }))
-            .await;
+            if !self.admit(client_addr.ip(), Direction::Download, reply.len()) {
+                self.send_event(Event::from(event::RateLimited {
+                    from_addr: self.remote_addr(),
+                    local_addr: self.local_addr(),
+                    to_addr: client_addr.clone(),
+                }))
+                .await;
+                continue;
+            }
+            let delivery = self.send_message_to_client(&reply, &client_addr).await;
+            self.record(&client_addr, Direction::Download, reply.as_bytes());
+            if let (Some(capture), Some((client, local, remote))) =
+                (&self.capture, self.inet_addrs(&client_addr))
+            {
+                capture.udp(remote, local, reply.as_bytes());
+                if matches!(delivery, Some(Delivery::Sent | Delivery::Held)) {
+                    capture.udp(local, client, reply.as_bytes());
+                }
+            }
+            let remote_addr = self.remote_addr();
+            self.report_delivery(delivery, remote_addr, client_addr, reply)
+                .await;
+        }
+    }
+
+    async fn report_delivery(
+        &self,
+        delivery: Option<Delivery>,
+        from_addr: Address,
+        to_addr: Address,
+        message: String,
+    ) -> bool {
+        let local_addr = self.local_addr();
+        match delivery {
+            Some(Delivery::Sent) => {
+                self.send_event(Event::from(event::Message {
+                    from_addr,
+                    local_addr,
+                    to_addr,
+                    message,
+                }))
+                .await;
+                true
+            }
+            Some(Delivery::Held) => {
+                self.send_event(Event::from(event::Held {
+                    from_addr,
+                    local_addr,
+                    to_addr,
+                    message,
+                }))
+                .await;
+                true
+            }
+            Some(Delivery::Dropped) => {
+                self.send_event(Event::from(event::Dropped {
+                    from_addr,
+                    local_addr,
+                    to_addr,
+                    message,
+                }))
+                .await;
+                false
+            }
+            None => false,
         }
     }
 
-    async fn get_message(&mut self) -> Option<(String, SocketAddr)> {
+    async fn get_message(&mut self) -> io::Result<(String, Address)> {
         if let Some(result) = self.queue.pop_front() {
-            Some(result)
+            Ok(result)
         } else {
             self.recv_message().await
         }
     }
 
-    async fn recv_message(&self) -> Option<(String, SocketAddr)> {
+    async fn recv_message(&mut self) -> io::Result<(String, Address)> {
         let mut buf = vec![0; self.buffer_size];
-        let (len, addr) = match self.socket.recv_from(&mut buf).await {
+        let (len, addr) = match self.relay.recv_from(&mut buf).await {
             Ok(result) => result,
             Err(error) => {
                 self.send_event(Event::from(event::MessageError {
                     from_addr: None,
                     local_addr: self.local_addr(),
                     to_addr: self.remote_addr(),
-                    error,
+                    error: io::Error::new(error.kind(), error.to_string()),
                 }))
                 .await;
-                return None;
+                return Err(error);
             }
         };
         let msg = String::from_utf8_lossy(&buf[..len]).to_string();
-        Some((msg, addr))
+        Ok((msg, addr))
     }
 
     async fn recv_reply(&mut self) -> Option<String> {
-        while let Some((msg, addr)) = self.recv_message().await {
+        while let Ok((msg, addr)) = self.recv_message().await {
             let is_remote_addr = self.remote_addrs.iter().any(|r| are_addrs_eq(r, &addr));
             if !is_remote_addr {
                 self.queue.push_back((msg, addr));
@@ -101,40 +241,107 @@ impl Proxy {
         None
     }
 
-    async fn send_message_to_client(&self, msg: &str, addr: &SocketAddr) {
-        if let Err(error) = self.socket.send_to(msg.as_bytes(), addr).await {
-            self.send_event(Event::from(event::MessageError {
-                from_addr: Some(self.remote_addr()),
-                local_addr: self.local_addr(),
-                to_addr: *addr,
-                error,
-            }))
-            .await;
+    async fn send_message_to_client(&mut self, msg: &str, addr: &Address) -> Option<Delivery> {
+        match self.send_datagram(msg.as_bytes(), addr).await {
+            Ok(delivery) => Some(delivery),
+            Err(error) => {
+                self.send_event(Event::from(event::MessageError {
+                    from_addr: Some(self.remote_addr()),
+                    local_addr: self.local_addr(),
+                    to_addr: addr.clone(),
+                    error,
+                }))
+                .await;
+                None
+            }
+        }
+    }
+
+    async fn send_message_to_remote(
+        &mut self,
+        msg: &str,
+        client_addr: &Address,
+    ) -> Option<Delivery> {
+        let mut datagram = match (&self.proxy_protocol, client_addr, self.local_addr()) {
+            (Some(proxy_protocol), Address::Inet(client_addr), Address::Inet(local_addr)) => {
+                proxy_protocol.encode(Transport::Datagram, *client_addr, local_addr)
+            }
+            (Some(proxy_protocol), _, _) => proxy_protocol.encode_local(),
+            (None, _, _) => Vec::new(),
+        };
+        datagram.extend_from_slice(msg.as_bytes());
+        match self.send_datagram(&datagram, &self.remote_addr()).await {
+            Ok(delivery) => Some(delivery),
+            Err(error) => {
+                self.send_event(Event::from(event::MessageError {
+                    from_addr: Some(client_addr.clone()),
+                    local_addr: self.local_addr(),
+                    to_addr: self.remote_addr(),
+                    error,
+                }))
+                .await;
+                None
+            }
         }
     }
 
-    async fn send_message_to_remote(&self, msg: &str, client_addr: &SocketAddr) {
-        if let Err(error) = self
-            .socket
-            .send_to(msg.as_bytes(), &*self.remote_addrs)
-            .await
-        {
-            self.send_event(Event::from(event::MessageError {
-                from_addr: Some(client_addr.clone()),
-                local_addr: self.local_addr(),
-                to_addr: self.remote_addr(),
-                error,
-            }))
-            .await;
+    async fn send_datagram(&mut self, datagram: &[u8], target: &Address) -> io::Result<Delivery> {
+        self.relay.send_to(datagram, target).await
+    }
+
+    fn admit(&self, client_ip: Option<IpAddr>, direction: Direction, len: usize) -> bool {
+        let client_buckets = client_ip.and_then(|ip| self.client_buckets.get(ip));
+        let client_packet_buckets = client_ip.and_then(|ip| self.client_packet_buckets.get(ip));
+        let byte_buckets = [client_buckets.as_deref(), Some(&self.global_buckets)];
+        let packet_buckets = [
+            client_packet_buckets.as_deref(),
+            Some(&self.global_packet_buckets),
+        ];
+        let bytes = byte_buckets
+            .into_iter()
+            .flatten()
+            .filter_map(|b| b.get(direction))
+            .map(|bucket| (bucket, len as u64));
+        let packets = packet_buckets
+            .into_iter()
+            .flatten()
+            .filter_map(|b| b.get(direction))
+            .map(|bucket| (bucket, 1));
+        limit::try_acquire_all(bytes.chain(packets))
+    }
+
+    fn record(&mut self, client_addr: &Address, direction: Direction, datagram: &[u8]) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let now = Instant::now();
+        if !self.recordings.contains_key(client_addr) {
+            self.recordings.retain(|_, (recording, last_seen)| {
+                let idle = now.duration_since(*last_seen) >= RECORDING_IDLE_TIMEOUT;
+                if idle {
+                    recorder.close(*recording);
+                }
+                !idle
+            });
         }
+        let (recording, last_seen) = self
+            .recordings
+            .entry(client_addr.clone())
+            .or_insert_with(|| (recorder.open(Protocol::Udp, client_addr), now));
+        *last_seen = now;
+        recorder.record(*recording, direction, datagram);
     }
 
-    fn local_addr(&self) -> SocketAddr {
-        self.socket.local_addr().unwrap()
+    fn inet_addrs(&self, client_addr: &Address) -> Option<(SocketAddr, SocketAddr, SocketAddr)> {
+        Some((
+            client_addr.inet()?,
+            self.local_addr().inet()?,
+            self.remote_addr().inet()?,
+        ))
     }
 
-    fn remote_addr(&self) -> SocketAddr {
-        self.remote_addrs.first().unwrap().clone()
+    fn remote_addr(&self) -> Address {
+        self.remote_addr.clone()
     }
 
     async fn send_event(&self, event: Event) {
@@ -143,3 +350,13 @@ impl Proxy {
         }
     }
 }
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        if let Some(recorder) = &self.recorder {
+            for (recording, _) in self.recordings.values() {
+                recorder.close(*recording);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::address::Address;
+use crate::fault::FaultInjector;
+use crate::net::DatagramSocket;
+use std::time::Duration;
+use tokio::io;
+use tokio::time::{sleep, sleep_until, Instant};
+
+const HOLD_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    Sent,
+    Dropped,
+    Held,
+}
+
+#[derive(Debug)]
+struct Held {
+    datagram: Vec<u8>,
+    target: Address,
+    deadline: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct Relay {
+    socket: DatagramSocket,
+    faults: FaultInjector,
+    held: Option<Held>,
+}
+
+impl Relay {
+    pub(crate) fn new(socket: DatagramSocket, faults: FaultInjector) -> Self {
+        Self {
+            socket,
+            faults,
+            held: None,
+        }
+    }
+
+    pub(crate) async fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, Address)> {
+        loop {
+            let Some(deadline) = self.held.as_ref().map(|held| held.deadline) else {
+                return self.socket.recv_from(buf).await;
+            };
+            tokio::select! {
+                received = self.socket.recv_from(buf) => return received,
+                _ = sleep_until(deadline) => {
+                    let _ = self.release_held().await;
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn send_to(
+        &mut self,
+        datagram: &[u8],
+        target: &Address,
+    ) -> io::Result<Delivery> {
+        let delay = self.faults.delay(datagram.len());
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        if self.faults.should_drop() {
+            return Ok(Delivery::Dropped);
+        }
+        let mut datagram = datagram.to_vec();
+        self.faults.corrupt(&mut datagram);
+        if self.held.is_none() && self.faults.should_reorder() {
+            self.held = Some(Held {
+                datagram,
+                target: target.clone(),
+                deadline: Instant::now() + HOLD_TIMEOUT,
+            });
+            return Ok(Delivery::Held);
+        }
+        let copies = if self.faults.should_duplicate() { 2 } else { 1 };
+        for _ in 0..copies {
+            self.socket.send_to(&datagram, target).await?;
+        }
+        self.release_held().await?;
+        Ok(Delivery::Sent)
+    }
+
+    async fn release_held(&mut self) -> io::Result<()> {
+        if let Some(held) = self.held.take() {
+            self.socket.send_to(&held.datagram, &held.target).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::Faults;
+    use tokio::net::UdpSocket;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn held_datagram_is_released_after_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = Address::Inet(peer.local_addr().unwrap());
+        let faults = FaultInjector::new(Faults::new().reorder_probability(1.0));
+        let mut relay = Relay::new(DatagramSocket::Udp(socket), faults);
+
+        let delivery = relay.send_to(b"last", &target).await.unwrap();
+        assert_eq!(delivery, Delivery::Held);
+
+        let mut buf = [0; 16];
+        let idle = timeout(HOLD_TIMEOUT * 4, relay.recv_from(&mut buf)).await;
+        assert!(idle.is_err());
+        let (len, _) = timeout(HOLD_TIMEOUT, peer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], b"last");
+    }
+
+    #[tokio::test]
+    async fn dropped_datagram_is_reported() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let faults = FaultInjector::new(Faults::new().drop_probability(1.0));
+        let mut relay = Relay::new(DatagramSocket::Udp(socket), faults);
+        let target = Address::Inet("127.0.0.1:9".parse().unwrap());
+        let delivery = relay.send_to(b"gone", &target).await.unwrap();
+        assert_eq!(delivery, Delivery::Dropped);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::record::{Protocol, RecordedConnection, Recording, ReplayMode};
+use crate::Direction;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::io;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+#[derive(Debug)]
+pub struct Replay {
+    socket: UdpSocket,
+    connections: Vec<RecordedConnection>,
+    mode: ReplayMode,
+    buffer_size: usize,
+}
+
+impl Replay {
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A,
+        recording: &Recording,
+        mode: ReplayMode,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr).await?,
+            connections: recording.connections(Protocol::Udp),
+            mode,
+            buffer_size: 65535,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub async fn run(self) {
+        let mut clients: HashMap<SocketAddr, (usize, usize)> = HashMap::new();
+        let mut buffer = vec![0; self.buffer_size];
+        while let Ok((len, client_addr)) = self.socket.recv_from(&mut buffer).await {
+            let datagram = &buffer[..len];
+            let position = match self.mode {
+                ReplayMode::ConnectionOrder => {
+                    let next_connection = clients.len();
+                    let (connection, chunk) =
+                        clients.entry(client_addr).or_insert((next_connection, 0));
+                    let Some(chunks) = self.connections.get(*connection).map(|c| &c.chunks) else {
+                        continue;
+                    };
+                    let Some(offset) = chunks[*chunk..]
+                        .iter()
+                        .position(|c| c.direction == Direction::Upload)
+                    else {
+                        continue;
+                    };
+                    *chunk += offset + 1;
+                    Some((*connection, *chunk))
+                }
+                ReplayMode::MatchRequests => {
+                    self.connections.iter().enumerate().find_map(|(i, c)| {
+                        c.chunks
+                            .iter()
+                            .position(|c| c.direction == Direction::Upload && c.bytes == datagram)
+                            .map(|position| (i, position + 1))
+                    })
+                }
+            };
+            let Some((connection, chunk)) = position else {
+                continue;
+            };
+            let replies = self.connections[connection].chunks[chunk..]
+                .iter()
+                .take_while(|c| c.direction == Direction::Download);
+            for reply in replies {
+                let _ = self.socket.send_to(&reply.bytes, client_addr).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn recording() -> Recording {
+        let text = "proxy-rs-recording 1\n\
+                    open 0 udp 127.0.0.1:4000 0\n\
+                    data 0 up 10 70696e67\n\
+                    data 0 down 20 706f6e67\n\
+                    data 0 up 30 627965\n\
+                    data 0 down 40 6f6b\n";
+        Recording::from_reader(text.as_bytes()).unwrap()
+    }
+
+    async fn exchange(socket: &UdpSocket, request: &[u8], response: &[u8]) {
+        socket.send(request).await.unwrap();
+        let mut buffer = [0; 16];
+        let len = timeout(Duration::from_secs(5), socket.recv(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[..len], response);
+    }
+
+    async fn start(mode: ReplayMode) -> UdpSocket {
+        let replay = Replay::bind("127.0.0.1:0", &recording(), mode)
+            .await
+            .unwrap();
+        let addr = replay.local_addr().unwrap();
+        tokio::spawn(replay.run());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(addr).await.unwrap();
+        socket
+    }
+
+    #[tokio::test]
+    async fn replays_connections_in_order() {
+        let socket = start(ReplayMode::ConnectionOrder).await;
+        exchange(&socket, b"anything", b"pong").await;
+        exchange(&socket, b"else", b"ok").await;
+    }
+
+    #[tokio::test]
+    async fn replays_matching_requests() {
+        let socket = start(ReplayMode::MatchRequests).await;
+        exchange(&socket, b"bye", b"ok").await;
+        exchange(&socket, b"ping", b"pong").await;
+    }
+}
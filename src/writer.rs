@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+enum Command {
+    Write(Vec<u8>),
+    Flush(mpsc::Sender<io::Result<()>>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FileWriter {
+    sender: mpsc::Sender<Command>,
+}
+
+impl FileWriter {
+    pub(crate) fn spawn(writer: BufWriter<File>) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("proxy-rs-writer".to_owned())
+            .spawn(move || run(writer, receiver))?;
+        Ok(Self { sender })
+    }
+
+    pub(crate) fn write(&self, bytes: Vec<u8>) {
+        let _ = self.sender.send(Command::Write(bytes));
+    }
+
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.sender
+            .send(Command::Flush(sender))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "writer stopped"))?;
+        receiver
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "writer stopped"))?
+    }
+}
+
+fn run(mut writer: BufWriter<File>, receiver: mpsc::Receiver<Command>) {
+    let mut failed: Option<io::Error> = None;
+    loop {
+        match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(Command::Write(bytes)) => {
+                if failed.is_none() {
+                    failed = writer.write_all(&bytes).err();
+                }
+            }
+            Ok(Command::Flush(reply)) => {
+                let result = match &failed {
+                    Some(error) => Err(io::Error::new(error.kind(), error.to_string())),
+                    None => writer.flush(),
+                };
+                let _ = reply.send(result);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = writer.flush();
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = writer.flush();
+                return;
+            }
+        }
+    }
+}
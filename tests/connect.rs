@@ -0,0 +1,100 @@
+use proxy_rs::assert_event;
+use proxy_rs::testing::{events, MemoryNetwork};
+use proxy_rs::{tcp, ConnectConfig};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+async fn start(net: &MemoryNetwork, event_sender: mpsc::Sender<tcp::Event>) {
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    let proxy = net
+        .tcp_proxy(addr("10.0.0.1:3128"), addr("10.0.0.2:7"))
+        .unwrap()
+        .connect(ConnectConfig::new().allow_port(7))
+        .handshake_timeout(Duration::from_millis(200))
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(proxy.run());
+}
+
+async fn exchange(net: &MemoryNetwork, request: &[u8]) -> Vec<u8> {
+    let mut client = net.connect(addr("10.0.0.1:3128")).await.unwrap();
+    client.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    response
+}
+
+#[tokio::test]
+async fn connect_tunnels_to_allowed_targets() {
+    let net = MemoryNetwork::new();
+    let (event_sender, mut events) = events();
+    start(&net, event_sender).await;
+
+    let mut client = net.connect(addr("10.0.0.1:3128")).await.unwrap();
+    client
+        .write_all(b"CONNECT 10.0.0.2:7 HTTP/1.1\r\nHost: 10.0.0.2:7\r\n\r\nhello")
+        .await
+        .unwrap();
+    let established = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+    let mut reply = vec![0; established.len() + 5];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply[..established.len()], established);
+    assert_eq!(&reply[established.len()..], b"hello");
+
+    let connection = assert_event!(events, tcp::Event::Connection(_));
+    let tcp::Event::Connection(connection) = connection else {
+        unreachable!();
+    };
+    assert_eq!(connection.remote_addr, addr("10.0.0.2:7").into());
+}
+
+#[tokio::test]
+async fn connect_rejects_targets_outside_the_allow_list() {
+    let net = MemoryNetwork::new();
+    let (event_sender, mut events) = events();
+    start(&net, event_sender).await;
+
+    let response = exchange(&net, b"CONNECT 10.0.0.2:22 HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+    assert_event!(events, tcp::Event::Rejected(_));
+}
+
+#[tokio::test]
+async fn connect_rejects_malformed_requests() {
+    let net = MemoryNetwork::new();
+    let (event_sender, _events) = events();
+    start(&net, event_sender).await;
+
+    let response = exchange(&net, b"CONNECT\r\n\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    let response = exchange(&net, b"CONNECT 10.0.0.2 HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[tokio::test]
+async fn connect_times_out_stalled_requests() {
+    let net = MemoryNetwork::new();
+    let (event_sender, mut events) = events();
+    start(&net, event_sender).await;
+
+    let response = exchange(&net, b"CONNECT 10.0.0.2:7 HTTP/1.1\r\n").await;
+    assert!(response.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+    let error = assert_event!(events, tcp::Event::ConnectionError(_));
+    let tcp::Event::ConnectionError(error) = error else {
+        unreachable!();
+    };
+    assert_eq!(error.error.kind(), io::ErrorKind::TimedOut);
+}
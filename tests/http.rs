@@ -0,0 +1,73 @@
+use proxy_rs::testing::{MemoryNetwork, Script};
+use proxy_rs::HttpConfig;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+#[tokio::test]
+async fn http_strips_hop_by_hop_headers() {
+    let net = MemoryNetwork::new();
+    let backend = net
+        .scripted(
+            addr("10.0.0.2:80"),
+            Script::new()
+                .expect("GET / HTTP/1.1\r\nX-Kept: 1\r\nHost: example.com\r\n\r\n")
+                .send("HTTP/1.1 200 OK\r\nConnection: X-Hop\r\nX-Hop: 1\r\nKeep-Alive: timeout=5\r\nContent-Length: 2\r\n\r\nok"),
+        )
+        .unwrap();
+    let proxy = net
+        .tcp_proxy(addr("10.0.0.1:8080"), addr("10.0.0.2:80"))
+        .unwrap()
+        .http(HttpConfig::new())
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(proxy.run());
+
+    let mut client = net.connect(addr("10.0.0.1:8080")).await.unwrap();
+    client
+        .write_all(
+            b"GET http://example.com/ HTTP/1.1\r\nConnection: keep-alive, X-Secret\r\n\
+              Keep-Alive: timeout=5\r\nTE: trailers\r\nProxy-Authorization: Basic abc\r\n\
+              X-Secret: 1\r\nX-Kept: 1\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let expected = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+    let mut response = vec![0; expected.len()];
+    client.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&response),
+        String::from_utf8_lossy(expected)
+    );
+    backend.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn http_times_out_stalled_request_heads() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:80")).unwrap();
+    let proxy = net
+        .tcp_proxy(addr("10.0.0.1:8080"), addr("10.0.0.2:80"))
+        .unwrap()
+        .http(HttpConfig::new())
+        .handshake_timeout(Duration::from_millis(200))
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(proxy.run());
+
+    let mut client = net.connect(addr("10.0.0.1:8080")).await.unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+}
@@ -0,0 +1,163 @@
+use proxy_rs::assert_event;
+use proxy_rs::record::Protocol;
+use proxy_rs::testing::{events, MemoryNetwork};
+use proxy_rs::{tcp, udp, Cidr, Direction, Event, Proxy, RateLimit};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+#[tokio::test]
+async fn tcp_forwards_to_echo_server() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    let (event_sender, mut events) = events();
+    let proxy = net
+        .tcp_proxy(addr("10.0.0.1:8080"), addr("10.0.0.2:7"))
+        .unwrap()
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(proxy.run());
+
+    let mut client = net.connect(addr("10.0.0.1:8080")).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut reply = [0; 5];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"hello");
+
+    assert_event!(events, tcp::Event::Connection(_));
+    assert_event!(
+        events,
+        tcp::Event::Message(message) if message.message == "hello"
+    );
+    drop(client);
+    assert_event!(events, tcp::Event::Disconnection(_));
+}
+
+#[tokio::test]
+async fn tcp_rejects_denied_clients() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    let (event_sender, events) = events();
+    let mut events = events.timeout(Duration::from_millis(200));
+    let proxy = net
+        .tcp_proxy(addr("10.0.0.1:8080"), addr("10.0.0.2:7"))
+        .unwrap()
+        .deny("127.0.0.0/8".parse::<Cidr>().unwrap())
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(proxy.run());
+
+    let mut client = net.connect(addr("10.0.0.1:8080")).await.unwrap();
+    let rejected = assert_event!(events, tcp::Event::Rejected(_));
+    let tcp::Event::Rejected(rejected) = rejected else {
+        unreachable!();
+    };
+    assert_eq!(rejected.client_addr.ip(), Some(addr("127.0.0.1:0").ip()));
+    let mut buf = [0; 1];
+    assert!(matches!(client.read(&mut buf).await, Ok(0) | Err(_)));
+    events
+        .assert_none(|event| matches!(event, tcp::Event::Connection(_)))
+        .await;
+}
+
+#[tokio::test]
+async fn udp_rate_limits_datagrams() {
+    let net = MemoryNetwork::new();
+    net.udp_echo(addr("10.0.0.2:7")).unwrap();
+    let (event_sender, mut events) = events();
+    let proxy = net
+        .udp_proxy(addr("10.0.0.1:5353"), addr("10.0.0.2:7"))
+        .unwrap()
+        .global_packet_rate_limit(Direction::Upload, RateLimit::new(1, 1))
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(proxy.run());
+
+    let client = net.bind(addr("127.0.0.1:40000")).unwrap();
+    let mut buf = [0; 16];
+    client.send_to(b"first", addr("10.0.0.1:5353")).unwrap();
+    let (len, _) = client.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], b"first");
+    client.send_to(b"second", addr("10.0.0.1:5353")).unwrap();
+
+    assert_event!(
+        events,
+        udp::Event::Message(message) if message.message == "first"
+    );
+    assert_event!(events, udp::Event::RateLimited(_));
+}
+
+#[tokio::test]
+async fn proxy_reports_listening_and_stopped() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    let (event_sender, mut events) = events();
+    let listener = net.listen(addr("10.0.0.1:8080")).unwrap();
+    let connector = net.connector();
+    let proxy = Proxy::builder()
+        .disable_udp()
+        .tcp(|tcp| {
+            tcp.listener(listener)
+                .connector(connector)
+                .remote_addrs(addr("10.0.0.2:7"))
+        })
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let handle = proxy.handle();
+    let running = tokio::spawn(proxy.run());
+
+    assert_event!(events, Event::Tcp(tcp::Event::Listening(_)));
+    handle.shutdown();
+    let stopped = assert_event!(events, Event::Stopped(_));
+    let Event::Stopped(stopped) = stopped else {
+        unreachable!();
+    };
+    assert_eq!(stopped.protocol, Protocol::Tcp);
+    assert!(stopped.result.is_ok());
+    assert!(running.await.unwrap().tcp.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn shutdown_closes_open_connections() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    let listener = net.listen(addr("10.0.0.1:8080")).unwrap();
+    let connector = net.connector();
+    let proxy = Proxy::builder()
+        .disable_udp()
+        .tcp(|tcp| {
+            tcp.listener(listener)
+                .connector(connector)
+                .remote_addrs(addr("10.0.0.2:7"))
+        })
+        .build()
+        .await
+        .unwrap();
+    let handle = proxy.handle();
+    let running = tokio::spawn(proxy.run());
+
+    let mut client = net.connect(addr("10.0.0.1:8080")).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut reply = [0; 5];
+    client.read_exact(&mut reply).await.unwrap();
+
+    handle.shutdown();
+    running.await.unwrap();
+    let mut buf = [0; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
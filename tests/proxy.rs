@@ -0,0 +1,331 @@
+use proxy_rs::record::Protocol;
+use proxy_rs::testing::{events, Events, MemoryNetwork};
+use proxy_rs::transport::{self, BoxFuture, Connection};
+use proxy_rs::{assert_event, tcp, udp, Address, Cidr, Error, Event, Faults, Proxy};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Debug)]
+struct FailingListener;
+
+impl transport::Listener for FailingListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(Box<dyn Connection>, Address)>> {
+        Box::pin(async { Err(io::Error::other("listener failed")) })
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        Ok(Address::Inet(addr("10.0.0.1:8080")))
+    }
+}
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+fn bound_port(addr: Option<Address>) -> SocketAddr {
+    let addr = addr.unwrap().inet().unwrap();
+    assert_ne!(addr.port(), 0);
+    addr
+}
+
+fn listening_addr(event: &Event) -> Option<(Option<&str>, &Address)> {
+    match event {
+        Event::Tcp(tcp::Event::Listening(listening)) => Some((None, &listening.local_addr)),
+        Event::Udp(udp::Event::Listening(listening)) => Some((None, &listening.local_addr)),
+        Event::Route(route) => {
+            listening_addr(&route.event).map(|(_, addr)| (Some(route.route.as_str()), addr))
+        }
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn port_zero_exposes_bound_addresses_before_run() {
+    let (event_sender, events) = events();
+    let mut events = events.timeout(Duration::from_millis(200));
+    let proxy = Proxy::builder()
+        .local_addrs("127.0.0.1:0")
+        .remote_addrs("127.0.0.1:9")
+        .tcp_route("admin", |tcp| {
+            tcp.local_addrs("127.0.0.1:0").remote_addrs("127.0.0.1:9")
+        })
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let tcp_addr = bound_port(proxy.tcp_local_addr());
+    let udp_addr = bound_port(proxy.udp_local_addr());
+    let route_addr = bound_port(proxy.route_local_addr("admin"));
+    assert_eq!(proxy.route_local_addr("missing"), None);
+    let handle = proxy.handle();
+    let running = tokio::spawn(proxy.run());
+
+    let mut listening = Vec::new();
+    while listening.len() < 3 {
+        let event = events
+            .wait_for(|event| listening_addr(event).is_some())
+            .await;
+        let (route, addr) = listening_addr(&event).unwrap();
+        listening.push((route.map(str::to_owned), addr.inet().unwrap()));
+    }
+    listening.sort();
+    let mut expected = vec![
+        (None, tcp_addr),
+        (None, udp_addr),
+        (Some("admin".to_owned()), route_addr),
+    ];
+    expected.sort();
+    assert_eq!(listening, expected);
+    events
+        .assert_none(|event| listening_addr(event).is_some())
+        .await;
+
+    handle.shutdown();
+    running.await.unwrap();
+}
+
+fn stopped(event: &Event) -> Option<(Protocol, &io::Result<()>)> {
+    match event {
+        Event::Stopped(stopped) => Some((stopped.protocol, &stopped.result)),
+        _ => None,
+    }
+}
+
+async fn drain_until_closed(events: &mut Events<Event>) -> Vec<Event> {
+    let mut drained = Vec::new();
+    while let Some(event) = events.next().await {
+        drained.push(event);
+    }
+    drained
+}
+
+#[tokio::test]
+async fn single_protocol_stops_on_shutdown() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    let (event_sender, mut events) = events();
+    let proxy = Proxy::builder()
+        .disable_udp()
+        .tcp(|tcp| {
+            tcp.listener(net.listen(addr("10.0.0.1:8080")).unwrap())
+                .connector(net.connector())
+                .remote_address(addr("10.0.0.2:7"))
+        })
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(proxy.udp_local_addr(), None);
+    let handle = proxy.handle();
+    let running = tokio::spawn(proxy.run());
+
+    let mut client = net.connect(addr("10.0.0.1:8080")).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut reply = [0; 5];
+    client.read_exact(&mut reply).await.unwrap();
+    handle.shutdown();
+    let exit = running.await.unwrap();
+    assert!(matches!(exit.tcp, Some(Ok(()))));
+    assert!(exit.udp.is_none());
+    assert!(exit.routes.is_empty());
+
+    // Everything the tcp side queued is delivered before its Stopped event,
+    // which is the last event and the only one.
+    let events = drain_until_closed(&mut events).await;
+    let (last, events) = events.split_last().unwrap();
+    let (protocol, result) = stopped(last).unwrap();
+    assert_eq!(protocol, Protocol::Tcp);
+    assert!(result.is_ok());
+    assert!(events.iter().all(|event| stopped(event).is_none()));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Event::Tcp(tcp::Event::Connection(_)))));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        Event::Tcp(tcp::Event::Message(message)) if message.message == "hello"
+    )));
+}
+
+#[tokio::test]
+async fn exit_reports_each_side() {
+    let net = MemoryNetwork::new();
+    let (event_sender, mut events) = events();
+    let proxy = Proxy::builder()
+        .tcp(|tcp| {
+            tcp.listener(FailingListener)
+                .remote_address(addr("10.0.0.2:7"))
+        })
+        .udp(|udp| {
+            udp.socket(net.bind(addr("10.0.0.1:5353")).unwrap())
+                .remote_address(addr("10.0.0.2:53"))
+        })
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let handle = proxy.handle();
+    let running = tokio::spawn(proxy.run());
+
+    let failed = assert_event!(
+        events,
+        Event::Stopped(stopped) if stopped.protocol == Protocol::Tcp
+    );
+    let Event::Stopped(failed) = failed else {
+        unreachable!();
+    };
+    assert_eq!(failed.result.unwrap_err().to_string(), "listener failed");
+
+    handle.shutdown();
+    let exit = running.await.unwrap();
+    let tcp = exit.tcp.unwrap().unwrap_err();
+    assert_eq!(tcp.kind(), io::ErrorKind::Other);
+    assert!(matches!(exit.udp, Some(Ok(()))));
+    let stopped = assert_event!(
+        events,
+        Event::Stopped(stopped) if stopped.protocol == Protocol::Udp
+    );
+    let Event::Stopped(stopped) = stopped else {
+        unreachable!();
+    };
+    assert!(stopped.result.is_ok());
+}
+
+fn route_event<'a>(event: &'a Event, name: &str) -> Option<&'a Event> {
+    match event {
+        Event::Route(route) if route.route == name => Some(&route.event),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn duplicate_route_names_are_rejected() {
+    let error = Proxy::builder()
+        .disable_tcp()
+        .disable_udp()
+        .tcp_route("web", |tcp| tcp.remote_address(addr("10.0.0.2:7")))
+        .udp_route("web", |udp| udp.remote_address(addr("10.0.0.2:53")))
+        .build()
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        Error::InvalidConfig("duplicate route name")
+    ));
+}
+
+#[tokio::test]
+async fn routes_share_events_access_list_and_faults() {
+    let net = MemoryNetwork::new();
+    net.echo(addr("10.0.0.2:7")).unwrap();
+    net.udp_echo(addr("10.0.0.2:53")).unwrap();
+    let (event_sender, mut events) = events();
+    let proxy = Proxy::builder()
+        .disable_tcp()
+        .disable_udp()
+        .tcp_route("web", |tcp| {
+            tcp.listener(net.listen(addr("10.0.0.1:80")).unwrap())
+                .connector(net.connector())
+                .remote_address(addr("10.0.0.2:7"))
+        })
+        .udp_route("dns", |udp| {
+            udp.socket(net.bind(addr("10.0.0.1:53")).unwrap())
+                .remote_address(addr("10.0.0.2:53"))
+        })
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let handle = proxy.handle();
+    let running = tokio::spawn(proxy.run());
+
+    let mut held = net.connect(addr("10.0.0.1:80")).await.unwrap();
+    held.write_all(b"hello").await.unwrap();
+    let mut reply = [0; 5];
+    held.read_exact(&mut reply).await.unwrap();
+    events
+        .wait_for(|event| {
+            matches!(
+                route_event(event, "web"),
+                Some(Event::Tcp(tcp::Event::Message(message))) if message.message == "hello"
+            )
+        })
+        .await;
+
+    let client = net.bind(addr("10.0.0.3:5000")).unwrap();
+    client.send_to(b"ping", addr("10.0.0.1:53")).unwrap();
+    let mut buf = [0; 4];
+    client.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    events
+        .wait_for(|event| {
+            matches!(
+                route_event(event, "dns"),
+                Some(Event::Udp(udp::Event::Message(_)))
+            )
+        })
+        .await;
+
+    handle
+        .faults()
+        .set_faults(Faults::new().drop_probability(1.0));
+    client.send_to(b"lost", addr("10.0.0.1:53")).unwrap();
+    events
+        .wait_for(|event| {
+            matches!(
+                route_event(event, "dns"),
+                Some(Event::Udp(udp::Event::Dropped(_)))
+            )
+        })
+        .await;
+    handle.faults().set_faults(Faults::new());
+
+    let access_list = handle.access_list();
+    access_list.deny("127.0.0.0/8".parse::<Cidr>().unwrap());
+    access_list.deny("10.0.0.3/32".parse::<Cidr>().unwrap());
+    let _rejected = net.connect(addr("10.0.0.1:80")).await.unwrap();
+    events
+        .wait_for(|event| {
+            matches!(
+                route_event(event, "web"),
+                Some(Event::Tcp(tcp::Event::Rejected(_)))
+            )
+        })
+        .await;
+    client.send_to(b"ping", addr("10.0.0.1:53")).unwrap();
+    events
+        .wait_for(|event| {
+            matches!(
+                route_event(event, "dns"),
+                Some(Event::Udp(udp::Event::Rejected(_)))
+            )
+        })
+        .await;
+
+    // Shutting down aborts the connection that is still open on "web".
+    handle.shutdown();
+    let exit = running.await.unwrap();
+    let routes: Vec<_> = exit
+        .routes
+        .iter()
+        .map(|(name, result)| (name.as_str(), result.is_ok()))
+        .collect();
+    assert_eq!(routes, [("web", true), ("dns", true)]);
+    let read = tokio::time::timeout(Duration::from_secs(5), held.read(&mut reply))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    // Each route's Stopped event comes after every other event of that route.
+    let events = drain_until_closed(&mut events).await;
+    for name in ["web", "dns"] {
+        let route_events: Vec<_> = events
+            .iter()
+            .filter_map(|event| route_event(event, name))
+            .collect();
+        let (last, rest) = route_events.split_last().unwrap();
+        assert!(stopped(last).is_some_and(|(_, result)| result.is_ok()));
+        assert!(rest.iter().all(|event| stopped(event).is_none()));
+    }
+}
@@ -0,0 +1,89 @@
+use proxy_rs::proxy_protocol::{self, ProxyProtocolMode};
+use proxy_rs::tcp::{self, Event};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start(mode: ProxyProtocolMode) -> (SocketAddr, TcpListener, mpsc::Receiver<Event>) {
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (event_sender, events) = mpsc::channel(64);
+    let proxy = tcp::Proxy::builder()
+        .local_addrs("127.0.0.1:0")
+        .remote_addrs(backend.local_addr().unwrap())
+        .accept_proxy_protocol(mode)
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let local_addr = proxy.local_addr().inet().unwrap();
+    tokio::spawn(proxy.run());
+    (local_addr, backend, events)
+}
+
+fn spoofed_header() -> Vec<u8> {
+    proxy_protocol::encode_v1(
+        "203.0.113.7:4000".parse().unwrap(),
+        "10.0.0.1:80".parse().unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn untrusted_header_is_rejected_when_required() {
+    let (local_addr, _backend, mut events) = start(ProxyProtocolMode::Required).await;
+    let mut client = TcpStream::connect(local_addr).await.unwrap();
+    client.write_all(&spoofed_header()).await.unwrap();
+
+    let rejected = timeout(TIMEOUT, async {
+        loop {
+            match events.recv().await.unwrap() {
+                Event::Rejected(rejected) => return rejected,
+                Event::Connection(connection) => panic!("connection accepted: {connection:?}"),
+                _ => {}
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        rejected.client_addr.ip(),
+        Some("127.0.0.1".parse().unwrap())
+    );
+    let mut buf = [0; 1];
+    let read = timeout(TIMEOUT, client.read(&mut buf)).await.unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn untrusted_header_is_ignored_when_optional() {
+    let (local_addr, backend, mut events) = start(ProxyProtocolMode::Optional).await;
+    let mut client = TcpStream::connect(local_addr).await.unwrap();
+    let header = spoofed_header();
+    client.write_all(&header).await.unwrap();
+
+    let (mut upstream, _) = timeout(TIMEOUT, backend.accept()).await.unwrap().unwrap();
+    let mut forwarded = vec![0; header.len()];
+    timeout(TIMEOUT, upstream.read_exact(&mut forwarded))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(forwarded, header);
+
+    let connection = timeout(TIMEOUT, async {
+        loop {
+            if let Event::Connection(connection) = events.recv().await.unwrap() {
+                return connection;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        connection.client_addr.ip(),
+        Some("127.0.0.1".parse().unwrap())
+    );
+}
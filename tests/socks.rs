@@ -0,0 +1,192 @@
+use proxy_rs::assert_event;
+use proxy_rs::testing::{events, Events};
+use proxy_rs::{tcp, Direction, RateLimit, Socks5Config};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start(builder: tcp::ProxyBuilder) -> (SocketAddr, Events<tcp::Event>) {
+    let (event_sender, events) = events();
+    let proxy = builder
+        .local_addrs("127.0.0.1:0")
+        .handshake_timeout(Duration::from_millis(200))
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let local_addr = proxy.local_addr().inet().unwrap();
+    tokio::spawn(proxy.run());
+    (local_addr, events)
+}
+
+async fn udp_echo_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        while let Ok((len, from_addr)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..len], from_addr).await;
+        }
+    });
+    addr
+}
+
+async fn associate(proxy_addr: SocketAddr) -> (TcpStream, SocketAddr) {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(&[5, 1, 0]).await.unwrap();
+    let mut method = [0; 2];
+    stream.read_exact(&mut method).await.unwrap();
+    assert_eq!(method, [5, 0]);
+    stream
+        .write_all(&[5, 3, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..4], [5, 0, 0, 1]);
+    let ip = <[u8; 4]>::try_from(&reply[4..8]).unwrap();
+    let port = u16::from_be_bytes([reply[8], reply[9]]);
+    (stream, SocketAddr::from((ip, port)))
+}
+
+fn datagram(target: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let SocketAddr::V4(target) = target else {
+        unreachable!();
+    };
+    let mut datagram = vec![0, 0, 0, 1];
+    datagram.extend_from_slice(&target.ip().octets());
+    datagram.extend_from_slice(&target.port().to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+#[tokio::test]
+async fn socks_associate_relays_datagrams() {
+    let echo_addr = udp_echo_server().await;
+    let (proxy_addr, mut events) = start(tcp::Proxy::builder().socks5(Socks5Config::new())).await;
+    let (stream, relay_addr) = associate(proxy_addr).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client
+        .send_to(&datagram(echo_addr, b"ping"), relay_addr)
+        .await
+        .unwrap();
+    let mut buf = [0; 64];
+    let (len, _) = timeout(TIMEOUT, client.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..len], datagram(echo_addr, b"ping"));
+
+    assert_event!(events, tcp::Event::Connection(_));
+    let upload = assert_event!(events, tcp::Event::Message(_));
+    let download = assert_event!(events, tcp::Event::Message(_));
+    let (tcp::Event::Message(upload), tcp::Event::Message(download)) = (upload, download) else {
+        unreachable!();
+    };
+    assert_eq!(upload.to_addr, echo_addr.into());
+    assert_eq!(download.from_addr, echo_addr.into());
+    assert_eq!(download.message, "ping");
+
+    drop(stream);
+    assert_event!(events, tcp::Event::Disconnection(_));
+}
+
+#[tokio::test]
+async fn socks_associate_drops_datagrams_from_unknown_peers() {
+    let echo_addr = udp_echo_server().await;
+    let (proxy_addr, _events) = start(tcp::Proxy::builder().socks5(Socks5Config::new())).await;
+    let (_stream, relay_addr) = associate(proxy_addr).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client
+        .send_to(&datagram(echo_addr, b"ping"), relay_addr)
+        .await
+        .unwrap();
+    let mut buf = [0; 64];
+    let (len, _) = timeout(TIMEOUT, client.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..len], datagram(echo_addr, b"ping"));
+
+    let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    stranger.send_to(b"spoofed", relay_addr).await.unwrap();
+    let received = timeout(Duration::from_millis(200), client.recv_from(&mut buf)).await;
+    assert!(received.is_err());
+}
+
+#[tokio::test]
+async fn socks_associate_applies_rate_limits() {
+    let echo_addr = udp_echo_server().await;
+    let (proxy_addr, mut events) = start(
+        tcp::Proxy::builder()
+            .socks5(Socks5Config::new())
+            .connection_rate_limit(Direction::Upload, RateLimit::new(1, 4)),
+    )
+    .await;
+    let (_stream, relay_addr) = associate(proxy_addr).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for payload in [b"ping", b"pong"] {
+        client
+            .send_to(&datagram(echo_addr, payload), relay_addr)
+            .await
+            .unwrap();
+    }
+    assert_event!(
+        events,
+        tcp::Event::Message(message) if message.message == "ping"
+    );
+    let limited = assert_event!(events, tcp::Event::RateLimited(_));
+    let tcp::Event::RateLimited(limited) = limited else {
+        unreachable!();
+    };
+    assert_eq!(limited.to_addr, echo_addr.into());
+}
+
+#[tokio::test]
+async fn socks_handshake_times_out() {
+    let (proxy_addr, mut events) = start(tcp::Proxy::builder().socks5(Socks5Config::new())).await;
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+
+    let error = assert_event!(events, tcp::Event::ConnectionError(_));
+    let tcp::Event::ConnectionError(error) = error else {
+        unreachable!();
+    };
+    assert_eq!(error.error.kind(), io::ErrorKind::TimedOut);
+    let mut buf = [0; 1];
+    let read = timeout(TIMEOUT, stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn socks_rejects_unknown_auth_versions() {
+    let (proxy_addr, mut events) =
+        start(tcp::Proxy::builder().socks5(Socks5Config::new().credentials("user", "secret")))
+            .await;
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(&[5, 1, 2]).await.unwrap();
+    let mut method = [0; 2];
+    stream.read_exact(&mut method).await.unwrap();
+    assert_eq!(method, [5, 2]);
+    let mut auth = vec![2, 4];
+    auth.extend_from_slice(b"user");
+    auth.push(6);
+    auth.extend_from_slice(b"secret");
+    stream.write_all(&auth).await.unwrap();
+
+    let error = assert_event!(events, tcp::Event::ConnectionError(_));
+    let tcp::Event::ConnectionError(error) = error else {
+        unreachable!();
+    };
+    assert_eq!(error.error.kind(), io::ErrorKind::InvalidData);
+    let mut buf = [0; 1];
+    let read = timeout(TIMEOUT, stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
@@ -0,0 +1,291 @@
+use proxy_rs::tcp::{self, Event};
+use proxy_rs::{TlsClientConfig, TlsServerConfig};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::RootCertStore;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Ca {
+    certificate: Certificate,
+    key: KeyPair,
+}
+
+struct Leaf {
+    certificate: Certificate,
+    key: KeyPair,
+}
+
+impl Ca {
+    fn new(name: &str) -> Self {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let certificate = params.self_signed(&key).unwrap();
+        Self { certificate, key }
+    }
+
+    fn issue(&self, common_name: &str, subject_alt_names: &[&str]) -> Leaf {
+        let key = KeyPair::generate().unwrap();
+        let subject_alt_names: Vec<String> = subject_alt_names
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let mut params = CertificateParams::new(subject_alt_names).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        let certificate = params
+            .signed_by(&key, &self.certificate, &self.key)
+            .unwrap();
+        Leaf { certificate, key }
+    }
+
+    fn pem(&self) -> String {
+        self.certificate.pem()
+    }
+
+    fn roots(&self) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.certificate.der().clone()).unwrap();
+        roots
+    }
+}
+
+impl Leaf {
+    fn chain(&self) -> Vec<CertificateDer<'static>> {
+        vec![self.certificate.der().clone()]
+    }
+
+    fn private_key(&self) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::try_from(self.key.serialize_der()).unwrap()
+    }
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn client_connector(ca: &Ca, identity: Option<&Leaf>) -> TlsConnector {
+    let builder = rustls::ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(ca.roots());
+    let config = match identity {
+        Some(leaf) => builder
+            .with_client_auth_cert(leaf.chain(), leaf.private_key())
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    TlsConnector::from(Arc::new(config))
+}
+
+async fn echo<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) {
+    let mut buffer = [0; 1024];
+    while let Ok(n) = stream.read(&mut buffer).await {
+        if n == 0 || stream.write_all(&buffer[..n]).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn plain_echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(echo(stream));
+        }
+    });
+    addr
+}
+
+async fn tls_echo_server(leaf: &Leaf) -> SocketAddr {
+    let config = rustls::ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(leaf.chain(), leaf.private_key())
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    echo(stream).await;
+                }
+            });
+        }
+    });
+    addr
+}
+
+async fn start(builder: tcp::ProxyBuilder) -> (SocketAddr, mpsc::Receiver<Event>) {
+    let (event_sender, events) = mpsc::channel(64);
+    let proxy = builder
+        .local_addrs("127.0.0.1:0")
+        .event_sender(event_sender)
+        .build()
+        .await
+        .unwrap();
+    let local_addr = proxy.local_addr().inet().unwrap();
+    tokio::spawn(proxy.run());
+    (local_addr, events)
+}
+
+async fn next_event<T>(
+    events: &mut mpsc::Receiver<Event>,
+    mut select: impl FnMut(Event) -> Option<T>,
+) -> T {
+    timeout(TIMEOUT, async {
+        loop {
+            if let Some(event) = select(events.recv().await.unwrap()) {
+                return event;
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+async fn round_trip<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> io::Result<()> {
+    stream.write_all(b"hello").await?;
+    let mut reply = [0; 5];
+    timeout(TIMEOUT, stream.read_exact(&mut reply)).await??;
+    assert_eq!(&reply, b"hello");
+    Ok(())
+}
+
+fn server_config(ca: &Ca) -> TlsServerConfig {
+    let leaf = ca.issue("proxy", &["localhost"]);
+    TlsServerConfig::new().certificate_pem(leaf.certificate.pem(), leaf.key.serialize_pem())
+}
+
+#[tokio::test]
+async fn terminates_tls_from_clients() {
+    let ca = Ca::new("test ca");
+    let backend = plain_echo_server().await;
+    let (local_addr, _events) = start(
+        tcp::Proxy::builder()
+            .remote_addrs(backend)
+            .tls(server_config(&ca)),
+    )
+    .await;
+
+    let stream = TcpStream::connect(local_addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut stream = client_connector(&ca, None)
+        .connect(server_name, stream)
+        .await
+        .unwrap();
+    round_trip(&mut stream).await.unwrap();
+}
+
+#[tokio::test]
+async fn originates_tls_to_upstreams() {
+    let ca = Ca::new("test ca");
+    let backend = tls_echo_server(&ca.issue("backend", &["localhost"])).await;
+    let upstream_tls = TlsClientConfig::new()
+        .ca_certificate_pem(ca.pem())
+        .server_name("localhost");
+    let (local_addr, _events) = start(
+        tcp::Proxy::builder()
+            .remote_addrs(backend)
+            .upstream_tls(upstream_tls),
+    )
+    .await;
+
+    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+    round_trip(&mut stream).await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_client_certificates_from_unknown_cas() {
+    let ca = Ca::new("test ca");
+    let other_ca = Ca::new("other ca");
+    let backend = plain_echo_server().await;
+    let tls = server_config(&ca).client_ca_pem(ca.pem());
+    let (local_addr, mut events) =
+        start(tcp::Proxy::builder().remote_addrs(backend).tls(tls)).await;
+
+    let stream = TcpStream::connect(local_addr).await.unwrap();
+    let identity = other_ca.issue("intruder", &[]);
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let connected = client_connector(&ca, Some(&identity))
+        .connect(server_name, stream)
+        .await;
+    if let Ok(mut stream) = connected {
+        assert!(round_trip(&mut stream).await.is_err());
+    }
+    next_event(&mut events, |event| match event {
+        Event::TlsError(error) => Some(error),
+        Event::Connection(connection) => panic!("connection accepted: {connection:?}"),
+        _ => None,
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn attaches_client_identity_to_events() {
+    let ca = Ca::new("test ca");
+    let backend = plain_echo_server().await;
+    let tls = server_config(&ca).client_ca_pem(ca.pem());
+    let (local_addr, mut events) =
+        start(tcp::Proxy::builder().remote_addrs(backend).tls(tls)).await;
+
+    let stream = TcpStream::connect(local_addr).await.unwrap();
+    let identity = ca.issue("alice", &["alice.example.com"]);
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut stream = client_connector(&ca, Some(&identity))
+        .connect(server_name, stream)
+        .await
+        .unwrap();
+    round_trip(&mut stream).await.unwrap();
+
+    let connection = next_event(&mut events, |event| match event {
+        Event::Connection(connection) => Some(connection),
+        _ => None,
+    })
+    .await;
+    let identity = connection.identity.unwrap();
+    assert_eq!(identity.subject, "CN=alice");
+    assert_eq!(identity.subject_alt_names, ["DNS:alice.example.com"]);
+    assert_eq!(identity.fingerprint.len(), 64);
+}
+
+#[tokio::test]
+async fn stalled_tls_handshake_is_dropped() {
+    let ca = Ca::new("test ca");
+    let backend = plain_echo_server().await;
+    let (local_addr, mut events) = start(
+        tcp::Proxy::builder()
+            .remote_addrs(backend)
+            .tls(server_config(&ca))
+            .handshake_timeout(Duration::from_millis(200)),
+    )
+    .await;
+
+    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+    let error = next_event(&mut events, |event| match event {
+        Event::TlsError(error) => Some(error),
+        _ => None,
+    })
+    .await;
+    assert_eq!(error.error.kind(), io::ErrorKind::TimedOut);
+    let mut buffer = [0; 1];
+    let read = timeout(TIMEOUT, stream.read(&mut buffer)).await.unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
@@ -0,0 +1,125 @@
+use proxy_rs::tcp::{self, Event};
+use proxy_rs::transport::{TurmoilConnector, TurmoilListener};
+use proxy_rs::{ConnectConfig, Direction, HttpConfig, RateLimit};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Instant};
+use turmoil::net::{TcpListener, TcpStream};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn any_addr(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+}
+
+async fn echo_server() -> turmoil::Result {
+    let listener = TcpListener::bind(any_addr(9000)).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let (mut reader, mut writer) = stream.into_split();
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        });
+    }
+}
+
+async fn proxies(event_sender: mpsc::Sender<Event>) -> turmoil::Result {
+    let remote_addr = SocketAddr::from((turmoil::lookup("server"), 9000));
+    let limited = tcp::Proxy::builder()
+        .listener(TurmoilListener::bind(any_addr(8000)).await?)
+        .connector(TurmoilConnector)
+        .remote_address(remote_addr)
+        .connection_rate_limit(Direction::Upload, RateLimit::new(1000, 1000))
+        .build()
+        .await?;
+    tokio::spawn(limited.run());
+    let http = tcp::Proxy::builder()
+        .listener(TurmoilListener::bind(any_addr(8080)).await?)
+        .connector(TurmoilConnector)
+        .remote_address(remote_addr)
+        .http(HttpConfig::new())
+        .handshake_timeout(HANDSHAKE_TIMEOUT)
+        .event_sender(event_sender)
+        .build()
+        .await?;
+    http.run().await?;
+    Ok(())
+}
+
+#[test]
+fn timeouts_and_limits_run_in_virtual_time() -> turmoil::Result {
+    let mut sim = turmoil::Builder::new()
+        .simulation_duration(Duration::from_secs(60))
+        .build();
+    let (event_sender, mut events) = mpsc::channel(64);
+    sim.host("server", echo_server);
+    sim.host("proxy", move || proxies(event_sender.clone()));
+    sim.client("client", async move {
+        let mut stream = TcpStream::connect(("proxy", 8000)).await?;
+        let started_at = Instant::now();
+        let payload = vec![7; 3000];
+        stream.write_all(&payload).await?;
+        let mut echoed = vec![0; payload.len()];
+        stream.read_exact(&mut echoed).await?;
+        assert_eq!(echoed, payload);
+        assert!(started_at.elapsed() >= Duration::from_secs(2));
+
+        let mut stream = TcpStream::connect(("proxy", 8080)).await?;
+        stream.write_all(b"GET / HTTP/1.1\r\n").await?;
+        let started_at = Instant::now();
+        turmoil::partition("client", "proxy");
+        let error = timeout(Duration::from_secs(30), async {
+            loop {
+                if let Some(Event::MessageError(error)) = events.recv().await {
+                    return error.error;
+                }
+            }
+        })
+        .await?;
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= HANDSHAKE_TIMEOUT && elapsed < HANDSHAKE_TIMEOUT * 2);
+        Ok(())
+    });
+
+    let started_at = std::time::Instant::now();
+    sim.run()?;
+    assert!(started_at.elapsed() < sim.elapsed());
+    Ok(())
+}
+
+#[test]
+fn connect_targets_resolve_through_the_connector() -> turmoil::Result {
+    let mut sim = turmoil::Builder::new().build();
+    sim.host("server", echo_server);
+    sim.host("proxy", || async {
+        let proxy = tcp::Proxy::builder()
+            .listener(TurmoilListener::bind(any_addr(8080)).await?)
+            .connector(TurmoilConnector)
+            .connect(ConnectConfig::new().allow_port(9000))
+            .build()
+            .await?;
+        proxy.run().await?;
+        Ok(())
+    });
+    sim.client("client", async {
+        let mut stream = TcpStream::connect(("proxy", 8080)).await?;
+        stream
+            .write_all(b"CONNECT server:9000 HTTP/1.1\r\nHost: server:9000\r\n\r\n")
+            .await?;
+        let established = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+        let mut response = vec![0; established.len()];
+        stream.read_exact(&mut response).await?;
+        assert_eq!(response, established);
+
+        stream.write_all(b"ping").await?;
+        let mut echoed = [0; 4];
+        stream.read_exact(&mut echoed).await?;
+        assert_eq!(&echoed, b"ping");
+        Ok(())
+    });
+    sim.run()
+}